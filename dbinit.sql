@@ -22,7 +22,11 @@ create table if not exists issuer
             primary key,
     "Name"     varchar(500) not null,
     "Symbol"   varchar(10)  not null,
-    cik        varchar(30)  not null
+    cik        varchar(30)  not null,
+    "Tickers"        varchar(10)[] default '{}' not null,
+    "Exchanges"      varchar(20)[] default '{}' not null,
+    "Sic"            varchar(4),
+    "SicDescription" varchar(200)
 );
 
 alter table issuer
@@ -31,6 +35,23 @@ alter table issuer
 create unique index if not exists issuer_symbol_cik_uindex
     on issuer ("Symbol", cik);
 
+create table if not exists issuer_former_name
+(
+    "IssuerId" integer      not null
+        constraint issuer_former_name_issuer_issuerid_fk
+            references issuer,
+    "Name"     varchar(500) not null,
+    "DateFrom" date,
+    "DateTo"   date,
+    constraint issuer_former_name_pk
+        primary key ("IssuerId", "Name")
+);
+
+alter table issuer_former_name
+    owner to postgres;
+
+create sequence if not exists "form_FormId_seq";
+
 create table if not exists form
 (
     "FormId"       bigint       default nextval('"form_FormId_seq"'::regclass) not null
@@ -43,7 +64,8 @@ create table if not exists form
     "FormType"     varchar(10)                                                 not null,
     "TxtURL"       varchar(500)                                                not null,
     "AccessNo"     varchar(500)                                                not null,
-    "WebURL"       varchar(500) default ''::character varying                  not null
+    "WebURL"       varchar(500) default ''::character varying                  not null,
+    "ParserVersion" integer     default 1                                      not null
 );
 
 alter table form
@@ -74,7 +96,8 @@ create table if not exists non_deriv_transaction
     "SharesTraded"    numeric(20, 3) not null,
     "AvgPrice"        numeric(20, 3) not null,
     "Amount"          numeric(20, 3) not null,
-    "Relationships"   integer[]      not null
+    "Relationships"   integer[]      not null,
+    "ParserVersion"   integer        default 1 not null
 );
 
 alter table non_deriv_transaction
@@ -83,6 +106,63 @@ alter table non_deriv_transaction
 create unique index if not exists non_deriv_transaction_formid_datereported_sharesbalance_uindex
     on non_deriv_transaction ("FormId", "DateReported", "SharesBalance");
 
+create table if not exists crawl_day
+(
+    "CrawlDate"  date                                   not null
+        constraint crawl_day_pk
+            primary key,
+    "Status"     varchar(20) default 'pending'          not null,
+    "Entries"    integer     default 0                  not null,
+    "Fetched"    integer     default 0                  not null,
+    "Parsed"     integer     default 0                  not null,
+    "Inserted"   integer     default 0                  not null,
+    "Failed"     integer     default 0                  not null,
+    "Attempts"   integer     default 0                  not null,
+    "Error"      varchar(500),
+    "StartedAt"  timestamp with time zone,
+    "FinishedAt" timestamp with time zone,
+    "UpdatedAt"  timestamp with time zone default now() not null,
+    "LeasedBy"     varchar(100),
+    "LeaseExpires" timestamp with time zone
+);
+
+alter table crawl_day
+    owner to postgres;
+
+create index if not exists crawl_day_status_index
+    on crawl_day ("Status");
+
+create table if not exists crawl_entry
+(
+    "AccessNo"  varchar(20)                            not null
+        constraint crawl_entry_pk
+            primary key,
+    "CrawlDate" date                                   not null,
+    "FilePath"  varchar(255)                           not null,
+    "FormType"  varchar(20)                            not null,
+    "Status"    varchar(20) default 'pending'          not null,
+    "Attempts"  integer     default 0                  not null,
+    "Error"     varchar(500),
+    "UpdatedAt" timestamp with time zone default now() not null
+);
+
+alter table crawl_entry
+    owner to postgres;
+
+create index if not exists crawl_entry_date_status_index
+    on crawl_entry ("CrawlDate", "Status");
+
+create table if not exists request_budget
+(
+    "Window"   timestamp with time zone not null
+        constraint request_budget_pk
+            primary key,
+    "Requests" integer default 0        not null
+);
+
+alter table request_budget
+    owner to postgres;
+
 create table if not exists __diesel_schema_migrations
 (
     version varchar(50)                         not null
@@ -1,4 +1,5 @@
 drop table non_deriv_transaction;
 drop table form;
+drop sequence if exists "form_FormId_seq";
 drop table issuer;
 drop table individual;
\ No newline at end of file
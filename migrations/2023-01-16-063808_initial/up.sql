@@ -31,6 +31,8 @@ alter table issuer
 create unique index if not exists issuer_symbol_cik_uindex
     on issuer ("Symbol", cik);
 
+create sequence if not exists "form_FormId_seq";
+
 create table if not exists form
 (
     "FormId"       bigint default nextval('"form_FormId_seq"'::regclass) not null
@@ -0,0 +1,5 @@
+alter table non_deriv_transaction
+    drop column "ParserVersion";
+
+alter table form
+    drop column "ParserVersion";
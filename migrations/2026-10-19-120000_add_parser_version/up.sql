@@ -0,0 +1,5 @@
+alter table form
+    add "ParserVersion" integer default 1 not null;
+
+alter table non_deriv_transaction
+    add "ParserVersion" integer default 1 not null;
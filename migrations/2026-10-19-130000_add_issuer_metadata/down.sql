@@ -0,0 +1,7 @@
+drop table if exists issuer_former_name;
+
+alter table issuer
+    drop column "Tickers",
+    drop column "Exchanges",
+    drop column "Sic",
+    drop column "SicDescription";
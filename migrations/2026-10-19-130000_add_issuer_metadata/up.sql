@@ -0,0 +1,17 @@
+alter table issuer
+    add "Tickers" varchar(10)[] default '{}' not null,
+    add "Exchanges" varchar(20)[] default '{}' not null,
+    add "Sic" varchar(4),
+    add "SicDescription" varchar(200);
+
+create table if not exists issuer_former_name
+(
+    "IssuerId" integer      not null
+        constraint issuer_former_name_issuer_issuerid_fk
+            references issuer,
+    "Name"     varchar(500) not null,
+    "DateFrom" date,
+    "DateTo"   date,
+    constraint issuer_former_name_pk
+        primary key ("IssuerId", "Name")
+);
@@ -0,0 +1 @@
+drop table if exists crawl_day;
@@ -0,0 +1,20 @@
+create table if not exists crawl_day
+(
+    "CrawlDate"  date                                   not null
+        constraint crawl_day_pk
+            primary key,
+    "Status"     varchar(20) default 'pending'          not null,
+    "Entries"    integer     default 0                  not null,
+    "Fetched"    integer     default 0                  not null,
+    "Parsed"     integer     default 0                  not null,
+    "Inserted"   integer     default 0                  not null,
+    "Failed"     integer     default 0                  not null,
+    "Attempts"   integer     default 0                  not null,
+    "Error"      varchar(500),
+    "StartedAt"  timestamp with time zone,
+    "FinishedAt" timestamp with time zone,
+    "UpdatedAt"  timestamp with time zone default now() not null
+);
+
+create index if not exists crawl_day_status_index
+    on crawl_day ("Status");
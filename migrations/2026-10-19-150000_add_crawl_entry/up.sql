@@ -0,0 +1,16 @@
+create table if not exists crawl_entry
+(
+    "AccessNo"  varchar(20)                            not null
+        constraint crawl_entry_pk
+            primary key,
+    "CrawlDate" date                                   not null,
+    "FilePath"  varchar(255)                           not null,
+    "FormType"  varchar(20)                            not null,
+    "Status"    varchar(20) default 'pending'          not null,
+    "Attempts"  integer     default 0                  not null,
+    "Error"     varchar(500),
+    "UpdatedAt" timestamp with time zone default now() not null
+);
+
+create index if not exists crawl_entry_date_status_index
+    on crawl_entry ("CrawlDate", "Status");
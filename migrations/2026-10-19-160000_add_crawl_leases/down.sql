@@ -0,0 +1,5 @@
+drop table if exists request_budget;
+
+alter table crawl_day
+    drop column if exists "LeaseExpires",
+    drop column if exists "LeasedBy";
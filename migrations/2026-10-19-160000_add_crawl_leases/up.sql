@@ -0,0 +1,11 @@
+alter table crawl_day
+    add column if not exists "LeasedBy"     varchar(100),
+    add column if not exists "LeaseExpires" timestamp with time zone;
+
+create table if not exists request_budget
+(
+    "Window"   timestamp with time zone not null
+        constraint request_budget_pk
+            primary key,
+    "Requests" integer default 0        not null
+);
@@ -0,0 +1,9 @@
+drop table if exists relationships;
+drop table if exists request_budget;
+drop table if exists crawl_entry;
+drop table if exists crawl_day;
+drop table if exists non_deriv_transaction;
+drop table if exists form;
+drop table if exists issuer_former_name;
+drop table if exists issuer;
+drop table if exists individual;
@@ -0,0 +1,149 @@
+-- The schema of migrations/ as of add_crawl_leases, for SQLite. Arrays are JSON
+-- text, timestamps UTC text in the layout diesel writes, numerics floating point.
+
+create table individual
+(
+    "IndividualId" integer      not null
+        constraint individual_pk
+            primary key,
+    cik            varchar(30)  not null,
+    "FullName"     varchar(500) not null,
+    "FirstName"    varchar(500),
+    "LastName"     varchar(500)
+);
+
+create unique index individual_cik_uindex
+    on individual (cik);
+
+create table issuer
+(
+    "IssuerId"       integer      not null
+        constraint issuer_pk
+            primary key,
+    "Name"           varchar(500) not null,
+    "Symbol"         varchar(10)  not null,
+    cik              varchar(30)  not null,
+    "Tickers"        text default '[]' not null,
+    "Exchanges"      text default '[]' not null,
+    "Sic"            varchar(4),
+    "SicDescription" varchar(200)
+);
+
+create unique index issuer_symbol_cik_uindex
+    on issuer ("Symbol", cik);
+
+create table issuer_former_name
+(
+    "IssuerId" integer      not null
+        constraint issuer_former_name_issuer_issuerid_fk
+            references issuer,
+    "Name"     varchar(500) not null,
+    "DateFrom" date,
+    "DateTo"   date,
+    constraint issuer_former_name_pk
+        primary key ("IssuerId", "Name")
+);
+
+create table form
+(
+    "FormId"        integer      not null
+        constraint form_pk
+            primary key,
+    "IssuerId"      integer      not null
+        constraint form_issuer_issuerid_fk
+            references issuer,
+    "DateReported"  date         not null,
+    "FormType"      varchar(10)  not null,
+    "TxtURL"        varchar(500) not null,
+    "AccessNo"      varchar(500) not null,
+    "WebURL"        varchar(500) default '' not null,
+    "ParserVersion" integer      default 1 not null
+);
+
+create unique index form_accessno_uindex
+    on form ("AccessNo");
+
+create table non_deriv_transaction
+(
+    "TransactionId"   integer not null
+        constraint non_deriv_transaction_pk
+            primary key,
+    "DateReported"    date    not null,
+    "FormId"          integer not null
+        constraint non_deriv_transaction_form_formid_fk
+            references form,
+    "IssuerId"        integer not null
+        constraint non_deriv_transaction_issuer_issuerid_fk
+            references issuer,
+    "IndividualId"    integer not null
+        constraint non_deriv_transaction_individual_individualid_fk
+            references individual,
+    "ActionCode"      char(1),
+    "OwnershipCode"   char(1),
+    "TransactionCode" char(1),
+    "SharesBalance"   numeric not null,
+    "SharesTraded"    numeric not null,
+    "AvgPrice"        numeric not null,
+    "Amount"          numeric not null,
+    "Relationships"   text    default '[]' not null,
+    "ParserVersion"   integer default 1 not null
+);
+
+create table crawl_day
+(
+    "CrawlDate"    date                                                       not null
+        constraint crawl_day_pk
+            primary key,
+    "Status"       varchar(20) default 'pending'                              not null,
+    "Entries"      integer     default 0                                      not null,
+    "Fetched"      integer     default 0                                      not null,
+    "Parsed"       integer     default 0                                      not null,
+    "Inserted"     integer     default 0                                      not null,
+    "Failed"       integer     default 0                                      not null,
+    "Attempts"     integer     default 0                                      not null,
+    "Error"        varchar(500),
+    "StartedAt"    text,
+    "FinishedAt"   text,
+    "UpdatedAt"    text default (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')) not null,
+    "LeasedBy"     varchar(100),
+    "LeaseExpires" text
+);
+
+create index crawl_day_status_index
+    on crawl_day ("Status");
+
+create table crawl_entry
+(
+    "AccessNo"  varchar(20)                                                not null
+        constraint crawl_entry_pk
+            primary key,
+    "CrawlDate" date                                                       not null,
+    "FilePath"  varchar(255)                                               not null,
+    "FormType"  varchar(20)                                                not null,
+    "Status"    varchar(20) default 'pending'                              not null,
+    "Attempts"  integer     default 0                                      not null,
+    "Error"     varchar(500),
+    "UpdatedAt" text default (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')) not null
+);
+
+create index crawl_entry_date_status_index
+    on crawl_entry ("CrawlDate", "Status");
+
+create table request_budget
+(
+    "Window"   text              not null
+        constraint request_budget_pk
+            primary key,
+    "Requests" integer default 0 not null
+);
+
+create table relationships
+(
+    "RelationshipId" integer      not null
+        constraint relationships_pk
+            primary key,
+    "Name"           varchar(100) not null
+);
+
+insert into relationships ("RelationshipId", "Name")
+values (1, 'OTHER'), (2, 'TEN PERCENT'), (3, 'DIRECTOR'), (4, 'OFFICER');
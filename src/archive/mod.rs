@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex}};
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tokio::task;
+use tracing::warn;
+
+use crate::secweb::IndexEntry;
+
+pub type SharedArchive = Arc<Mutex<Archive>>;
+
+/// Runs `f` on the shared archive off the async runtime, its gzip IO blocks
+/// and so does waiting for the lock.
+pub async fn run<T, F>(archive: &SharedArchive, f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Archive) -> T + Send + 'static,
+{
+    let archive = archive.clone();
+    task::spawn_blocking(move || f(&mut archive.lock().unwrap()))
+        .await
+        .expect("Archive task panicked")
+}
+
+const INDEX_FILE: &str = "index.ndjson";
+const OBJECTS_DIR: &str = "objects";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RawKind {
+    DailyIndex,
+    FullIndex,
+    Filing,
+}
+
+/// One line of the archive index. `key` is the accession number for filings,
+/// the index file name (e.g. `master.20230117.idx`) for daily indexes and the
+/// index path (e.g. `Archives/edgar/full-index/2023/QTR1/master.idx`) for quarterly ones.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ArchiveRecord {
+    pub key: String,
+    pub kind: RawKind,
+    pub sha256: String,
+    pub size: usize,
+    pub date: NaiveDate,
+    pub form_type: Option<String>,
+    pub company_cik: Option<String>,
+    pub filepath: Option<String>,
+    pub stored_at: DateTime<Utc>,
+}
+
+/// Local store of raw EDGAR documents. Bodies are gzipped and written once per
+/// distinct SHA-256 under `objects/`, and `index.ndjson` maps keys to hashes so
+/// the same document fetched twice only takes space once.
+pub struct Archive {
+    root: PathBuf,
+    records: HashMap<String, ArchiveRecord>,
+}
+
+impl Archive {
+    pub fn open<P: AsRef<Path>>(root: P) -> io::Result<Archive> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join(OBJECTS_DIR))?;
+
+        let mut records = HashMap::new();
+        let index_path = root.join(INDEX_FILE);
+
+        if index_path.exists() {
+            let rdr = BufReader::new(File::open(&index_path)?);
+            for line in rdr.lines() {
+                let line = line?;
+                match serde_json::from_str::<ArchiveRecord>(&line) {
+                    // later lines win so a re-archived document points at its newest body
+                    Ok(record) => { records.insert(record.key.clone(), record); },
+                    Err(err) => warn!(%err, "Skipping bad archive index line"),
+                }
+            }
+        }
+
+        Ok(Archive { root, records })
+    }
+
+    pub fn shared(self) -> SharedArchive {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.records.contains_key(key)
+    }
+
+    pub fn record(&self, key: &str) -> Option<&ArchiveRecord> {
+        self.records.get(key)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &ArchiveRecord> {
+        self.records.values()
+    }
+
+    pub fn put_filing(&mut self, entry: &IndexEntry, body: &str) -> io::Result<ArchiveRecord> {
+        self.put(ArchiveRecord {
+            key: entry.access_no(),
+            kind: RawKind::Filing,
+            sha256: String::new(),
+            size: body.len(),
+            date: entry.file_date,
+            form_type: Some(entry.form_type.clone()),
+            company_cik: Some(entry.company_cik.clone()),
+            filepath: Some(entry.filepath.clone()),
+            stored_at: Utc::now(),
+        }, body)
+    }
+
+    pub fn put_daily_index(&mut self, name: &str, date: NaiveDate, body: &str) -> io::Result<ArchiveRecord> {
+        self.put_index(RawKind::DailyIndex, name, date, body)
+    }
+
+    /// `date` is the first day of the quarter the index covers.
+    pub fn put_full_index(&mut self, name: &str, date: NaiveDate, body: &str) -> io::Result<ArchiveRecord> {
+        self.put_index(RawKind::FullIndex, name, date, body)
+    }
+
+    fn put_index(&mut self, kind: RawKind, name: &str, date: NaiveDate, body: &str) -> io::Result<ArchiveRecord> {
+        self.put(ArchiveRecord {
+            key: name.to_string(),
+            kind,
+            sha256: String::new(),
+            size: body.len(),
+            date,
+            form_type: None,
+            company_cik: None,
+            filepath: None,
+            stored_at: Utc::now(),
+        }, body)
+    }
+
+    /// Raw body stored under `key`, if any.
+    pub fn get(&self, key: &str) -> io::Result<Option<String>> {
+        match self.records.get(key) {
+            Some(record) => self.read_object(&record.sha256).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn read_object(&self, sha256: &str) -> io::Result<String> {
+        let mut body = String::new();
+        GzDecoder::new(File::open(self.object_path(sha256))?)
+            .read_to_string(&mut body)?;
+
+        Ok(body)
+    }
+
+    fn put(&mut self, mut record: ArchiveRecord, body: &str) -> io::Result<ArchiveRecord> {
+        record.sha256 = hex::encode(Sha256::digest(body.as_bytes()));
+
+        if let Some(existing) = self.records.get(&record.key) {
+            if existing.sha256 == record.sha256 {
+                return Ok(existing.clone());
+            }
+        }
+
+        self.write_object(&record.sha256, body)?;
+
+        let mut index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.root.join(INDEX_FILE))?;
+        let line = serde_json::to_string(&record)?;
+        writeln!(index, "{line}")?;
+
+        self.records.insert(record.key.clone(), record.clone());
+        Ok(record)
+    }
+
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.root
+            .join(OBJECTS_DIR)
+            .join(&sha256[..2])
+            .join(format!("{sha256}.gz"))
+    }
+
+    fn write_object(&self, sha256: &str, body: &str) -> io::Result<()> {
+        let path = self.object_path(sha256);
+        if path.exists() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        // write to a temp file first so a crash never leaves a truncated object
+        let tmp = path.with_extension("tmp");
+        let mut encoder = GzEncoder::new(File::create(&tmp)?, Compression::default());
+        encoder.write_all(body.as_bytes())?;
+        encoder.finish()?;
+
+        fs::rename(tmp, path)
+    }
+}
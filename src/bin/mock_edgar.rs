@@ -0,0 +1,78 @@
+//! Minimal stand-in for www.sec.gov that serves files from a fixtures
+//! directory laid out like EDGAR, e.g.
+//! `fixtures/edgar/Archives/edgar/daily-index/2023/QTR1/master.20230117.idx`.
+//!
+//! `mock_edgar [fixtures dir] [listen addr]`, then point the crawler at it with
+//! `EDGAR_BASE_URL=http://127.0.0.1:8088/`.
+
+use std::{
+    convert::Infallible,
+    env,
+    net::SocketAddr,
+    path::{Component, Path, PathBuf}};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode};
+
+const DEFAULT_FIXTURES: &str = "fixtures/edgar";
+const DEFAULT_ADDR: &str = "127.0.0.1:8088";
+
+fn resolve(root: &Path, uri_path: &str) -> Option<PathBuf> {
+    let relative = Path::new(uri_path.trim_start_matches('/'));
+
+    // only plain path segments, never `..` or absolute components
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+
+    Some(root.join(relative))
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::from(code.canonical_reason().unwrap_or_default()))
+        .unwrap()
+}
+
+async fn serve(root: PathBuf, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::GET {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let Some(path) = resolve(&root, req.uri().path()) else {
+        return Ok(status(StatusCode::BAD_REQUEST));
+    };
+
+    let response = match tokio::fs::read(&path).await {
+        Ok(body) => Response::new(Body::from(body)),
+        Err(_) => status(StatusCode::NOT_FOUND),
+    };
+
+    println!("{} {} {}", req.method(), req.uri(), response.status().as_u16());
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let root = PathBuf::from(args.next().unwrap_or_else(|| DEFAULT_FIXTURES.to_string()));
+    let addr: SocketAddr = args
+        .next()
+        .unwrap_or_else(|| DEFAULT_ADDR.to_string())
+        .parse()
+        .expect("Listen address must look like 127.0.0.1:8088");
+
+    let make_svc = make_service_fn(move |_| {
+        let root = root.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| serve(root.clone(), req)))
+        }
+    });
+
+    println!("Mock EDGAR listening on http://{addr}/");
+    Server::bind(&addr)
+        .serve(make_svc)
+        .await
+        .expect("Mock EDGAR server failed");
+}
@@ -0,0 +1,339 @@
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration};
+use dotenvy::dotenv;
+use reqwest::Url;
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::{crawler::Sink, secweb::{SEC_BASEURL, SEC_DATA_BASEURL}};
+
+/// Read when no `--config` is given and it exists.
+pub const DEFAULT_PATH: &str = "spysec.toml";
+const ENV_PREFIX: &str = "SPYSEC_";
+/// Names in use before the config file, still honored
+const LEGACY_ENV: [(&str, &str, &str); 3] = [
+    ("DATABASE_URL", "database", "url"),
+    ("EDGAR_BASE_URL", "edgar", "base_url"),
+    ("EDGAR_DATA_URL", "edgar", "data_url"),
+];
+
+/// Everything configurable, read from a TOML file with `SPYSEC_<SECTION>_<KEY>`
+/// environment overrides, e.g. `SPYSEC_DATABASE_POOL_SIZE=4`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub edgar: EdgarConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    #[serde(default)]
+    pub crawl: CrawlConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EdgarConfig {
+    /// Sent with every request, the SEC asks for a name and contact email.
+    /// Required, there is no default.
+    pub user_agent: String,
+    pub base_url: String,
+    /// data.sec.gov, or `base_url` when only that points at a mirror
+    pub data_url: Option<String>,
+    /// Requests per second of a single crawler, the SEC allows at most 10
+    pub requests_per_second: u8,
+    /// Requests per second shared by all workers
+    pub shared_requests_per_second: u8,
+    pub form_types: Vec<String>,
+}
+
+impl Default for EdgarConfig {
+    fn default() -> Self {
+        EdgarConfig {
+            user_agent: String::new(),
+            base_url: SEC_BASEURL.to_string(),
+            data_url: None,
+            requests_per_second: 8,
+            shared_requests_per_second: 10,
+            form_types: vec!["4".to_string()],
+        }
+    }
+}
+
+impl EdgarConfig {
+    pub fn data_url(&self) -> &str {
+        match &self.data_url {
+            Some(url) => url,
+            None if self.base_url != SEC_BASEURL => &self.base_url,
+            None => SEC_DATA_BASEURL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Daily JSON files and failed.txt
+    pub filings_dir: PathBuf,
+    /// Raw documents, indexes and feed tarballs
+    pub archive_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig { filings_dir: PathBuf::from("filings"), archive_dir: PathBuf::from("archive") }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Postgres, or SQLite for `sqlite://` URLs and `.db` paths
+    pub url: Option<String>,
+    pub pool_size: u32,
+    /// Also how long SQLite writers wait for each other
+    pub connect_timeout_secs: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig { url: None, pool_size: 10, connect_timeout_secs: 30 }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlConfig {
+    /// Where parsed filings are written unless `--sink` is given
+    pub sinks: Vec<Sink>,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        CrawlConfig { sinks: vec![Sink::Database, Sink::Json] }
+    }
+}
+
+impl Config {
+    /// Reads `path`, or `spysec.toml` when it exists, applies environment
+    /// overrides and validates the result. `.env` is loaded first.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        dotenv().ok();
+
+        let mut table = match path {
+            Some(path) => Self::read(path)?,
+            None if Path::new(DEFAULT_PATH).exists() => Self::read(Path::new(DEFAULT_PATH))?,
+            None => Table::new(),
+        };
+
+        Self::apply_env(&mut table, env::vars())?;
+
+        let config: Config = table.try_into()
+            .map_err(|err| format!("Invalid configuration: {}", err.to_string().trim_end()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Table, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Could not read config {}: {err}", path.display()))?;
+
+        text.parse::<Table>()
+            .map_err(|err| format!("Could not parse config {}: {err}", path.display()).into())
+    }
+
+    /// Sets `SPYSEC_<SECTION>_<KEY>` and the legacy variables on top of the
+    /// file. Values are read as TOML when they parse, so lists and numbers
+    /// work, and as plain strings otherwise.
+    fn apply_env(table: &mut Table, vars: impl Iterator<Item = (String, String)>) -> Result<(), Box<dyn Error>> {
+        for (name, raw) in vars {
+            let target = match LEGACY_ENV.iter().find(|(legacy, _, _)| *legacy == name) {
+                Some((_, section, key)) => Some((section.to_string(), key.to_string())),
+                None => name.strip_prefix(ENV_PREFIX)
+                    .filter(|rest| *rest != "CONFIG")
+                    .map(|rest| rest.to_lowercase())
+                    .map(|rest| match rest.split_once('_') {
+                        Some((section, key)) => (section.to_string(), key.to_string()),
+                        None => (rest, String::new()),
+                    }),
+            };
+
+            let Some((section, key)) = target else {
+                continue;
+            };
+
+            if key.is_empty() {
+                return Err(format!("{name} does not name a setting, use {ENV_PREFIX}<SECTION>_<KEY>").into());
+            }
+
+            let value = format!("value = {raw}").parse::<Table>()
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or(Value::String(raw));
+
+            let section = table.entry(section.clone())
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| format!("Config key {section} must be a table"))?;
+            section.insert(key, value);
+        }
+
+        Ok(())
+    }
+
+    /// Reports every problem at once rather than the first one.
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut problems = Vec::new();
+
+        let edgar = &self.edgar;
+        if edgar.user_agent.trim().is_empty() {
+            problems.push("edgar.user_agent is required, set it to your name and contact email".to_string());
+        } else if !edgar.user_agent.contains('@') {
+            problems.push("edgar.user_agent must include a contact email, the SEC blocks anonymous clients".to_string());
+        }
+
+        for (key, url) in [("edgar.base_url", edgar.base_url.as_str()), ("edgar.data_url", edgar.data_url())] {
+            if let Err(err) = Url::parse(url) {
+                problems.push(format!("{key} is not a valid URL ({err}): {url}"));
+            }
+        }
+
+        for (key, rate) in [
+            ("edgar.requests_per_second", edgar.requests_per_second),
+            ("edgar.shared_requests_per_second", edgar.shared_requests_per_second),
+        ] {
+            if !(1..=10).contains(&rate) {
+                problems.push(format!("{key} must be between 1 and 10, the SEC's limit, got {rate}"));
+            }
+        }
+
+        if edgar.form_types.is_empty() {
+            problems.push("edgar.form_types must list at least one form type".to_string());
+        }
+
+        if self.database.pool_size == 0 {
+            problems.push("database.pool_size must be at least 1".to_string());
+        }
+
+        if self.crawl.sinks.is_empty() {
+            problems.push("crawl.sinks must list at least one sink".to_string());
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(format!("Invalid configuration:\n  {}", problems.join("\n  ")).into()),
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Makes `config` the one returned by `config()`. Only the first call wins.
+pub fn init(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+/// The configuration passed to `init`, or the default file and environment
+/// when the library is used without it.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load(None).unwrap_or_else(|err| panic!("{err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect::<Vec<_>>().into_iter()
+    }
+
+    fn config(table: Table) -> Config {
+        table.try_into().unwrap()
+    }
+
+    #[test]
+    fn env_overrides_the_file() {
+        let mut table: Table = r#"
+            [edgar]
+            user_agent = "From File file@example.com"
+            requests_per_second = 2
+
+            [database]
+            pool_size = 8
+        "#.parse().unwrap();
+
+        Config::apply_env(&mut table, vars(&[
+            ("SPYSEC_EDGAR_USER_AGENT", "Jane Doe jane@example.com"),
+            ("SPYSEC_DATABASE_POOL_SIZE", "4"),
+            ("SPYSEC_EDGAR_FORM_TYPES", r#"["4", "4/A"]"#),
+            ("SPYSEC_CONFIG", "ignored.toml"),
+            ("HOME", "/root"),
+        ])).unwrap();
+
+        let config = config(table);
+        assert_eq!(config.edgar.user_agent, "Jane Doe jane@example.com");
+        assert_eq!(config.edgar.requests_per_second, 2);
+        assert_eq!(config.edgar.form_types, ["4", "4/A"]);
+        assert_eq!(config.database.pool_size, 4);
+    }
+
+    #[test]
+    fn legacy_variables_still_apply() {
+        let mut table = Table::new();
+        Config::apply_env(&mut table, vars(&[
+            ("DATABASE_URL", "postgres://localhost/sec"),
+            ("EDGAR_BASE_URL", "http://127.0.0.1:8089/"),
+        ])).unwrap();
+
+        let config = config(table);
+        assert_eq!(config.database.url.as_deref(), Some("postgres://localhost/sec"));
+        assert_eq!(config.edgar.base_url, "http://127.0.0.1:8089/");
+        // data.sec.gov follows a mirrored base URL
+        assert_eq!(config.edgar.data_url(), "http://127.0.0.1:8089/");
+    }
+
+    #[test]
+    fn env_without_a_key_is_refused() {
+        let err = Config::apply_env(&mut Table::new(), vars(&[("SPYSEC_DATABASE", "x")])).unwrap_err();
+        assert!(err.to_string().contains("SPYSEC_DATABASE"), "{err}");
+    }
+
+    #[test]
+    fn user_agent_is_required() {
+        let err = config(Table::new()).validate().unwrap_err();
+        assert!(err.to_string().contains("edgar.user_agent is required"), "{err}");
+
+        let mut table = Table::new();
+        Config::apply_env(&mut table, vars(&[("SPYSEC_EDGAR_USER_AGENT", "no contact")])).unwrap();
+        let err = config(table).validate().unwrap_err();
+        assert!(err.to_string().contains("contact email"), "{err}");
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut table = Table::new();
+        Config::apply_env(&mut table, vars(&[
+            ("SPYSEC_EDGAR_USER_AGENT", "Jane Doe jane@example.com"),
+            ("SPYSEC_EDGAR_REQUESTS_PER_SECOND", "11"),
+            ("SPYSEC_DATABASE_POOL_SIZE", "0"),
+        ])).unwrap();
+
+        let err = config(table).validate().unwrap_err().to_string();
+        assert!(err.contains("edgar.requests_per_second"), "{err}");
+        assert!(err.contains("database.pool_size"), "{err}");
+        assert!(!err.contains("user_agent"), "{err}");
+    }
+}
@@ -0,0 +1,150 @@
+use std::{collections::{BTreeMap, HashSet}, sync::atomic::Ordering};
+use chrono::{Datelike, Months, NaiveDate};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::secweb::{calendar::is_business_day, full_index_name, parse_daily_index, IndexEntry};
+
+use crate::{archive, database::crawl_state::{CrawlStatus, DayCounts}, metrics::count_index};
+
+use super::{Crawler, Direction};
+
+impl Crawler {
+    fn quarter_start(date: NaiveDate) -> NaiveDate {
+        NaiveDate::from_ymd_opt(date.year(), date.month0() / 3 * 3 + 1, 1).unwrap()
+    }
+
+    /// Quarterly master index starting at `quarter`, `None` when EDGAR has
+    /// none. Only finished quarters are served from the archive since the
+    /// current one still grows every day.
+    async fn get_full_index(&self, quarter: NaiveDate) -> Result<Option<String>, String> {
+        let name = full_index_name(quarter);
+        let finished = quarter + Months::new(3) <= Self::yesterday();
+
+        if finished {
+            let key = name.clone();
+            let archived = archive::run(&self.archive, move |archive| archive.get(&key)).await;
+            if let Ok(Some(body)) = archived {
+                info!(%name, "Using archived index");
+                count_index("full", "archived");
+                return Ok(Some(body));
+            }
+        }
+
+        if let Some(budget) = &self.budget {
+            budget.acquire(1).await;
+        }
+
+        let body = match self.edgar.fetch_full_index(quarter).await {
+            Ok(Some(body)) => {
+                count_index("full", "ok");
+                body
+            },
+            Ok(None) => {
+                count_index("full", "missing");
+                return Ok(None);
+            },
+            Err(err) => {
+                count_index("full", "error");
+                return Err(err.to_string());
+            }
+        };
+
+        let key = name.clone();
+        let (stored, body) = archive::run(&self.archive, move |archive| {
+            (archive.put_full_index(&key, quarter, &body), body)
+        }).await;
+        if let Err(err) = stored {
+            warn!(%name, %err, "Could not archive index");
+        }
+
+        Ok(Some(body))
+    }
+
+    /// Records the business days of `quarter` in the range as failed, so
+    /// resumed crawls and workers pick them up again.
+    async fn fail_quarter(&mut self, quarter: NaiveDate, from: NaiveDate, to: NaiveDate, error: &str) {
+        let days = quarter.iter_days()
+            .take_while(|day| *day < quarter + Months::new(3) && *day <= to)
+            .filter(|day| *day >= from && is_business_day(*day));
+
+        for day in days {
+            self.crawl_date = day;
+            self.start_day().await;
+            self.finish_day(CrawlStatus::Failed, DayCounts::default(), Some(error.to_string())).await;
+        }
+    }
+
+    /// Crawl every filing in the range using one quarterly full index per
+    /// quarter instead of a daily index request per calendar day. Returns how
+    /// many days failed, those of a quarter without index included.
+    pub async fn backfill(&mut self, batch: usize) -> usize {
+        let from = self.from;
+        let to = self.to.unwrap_or_else(Self::yesterday);
+
+        let mut quarters = Vec::new();
+        let mut quarter = Self::quarter_start(from);
+        while quarter <= to {
+            quarters.push(quarter);
+            quarter = quarter + Months::new(3);
+        }
+
+        if self.direction == Direction::Backward {
+            quarters.reverse();
+        }
+
+        for quarter in quarters {
+            if self.shutdown.is_triggered() {
+                break;
+            }
+            let entries = match self.get_full_index(quarter).await {
+                Ok(index) => index
+                    .map(|index| parse_daily_index(&index, &self.form_types))
+                    .unwrap_or_default(),
+                Err(err) => {
+                    let name = full_index_name(quarter);
+                    error!(%name, %err, "Error fetching full index");
+                    self.fail_quarter(quarter, from, to, &format!("Error fetching {name}: {err}")).await;
+                    continue;
+                }
+            };
+
+            // the index lists a filing once per filer, fetch each one only once
+            let mut seen = HashSet::new();
+            let mut days = BTreeMap::<NaiveDate, Vec<IndexEntry>>::new();
+            for entry in entries {
+                if entry.file_date >= from && entry.file_date <= to && seen.insert(entry.access_no()) {
+                    days.entry(entry.file_date).or_default().push(entry);
+                }
+            }
+
+            info!(days = days.len(), index = %full_index_name(quarter), "Backfilling quarter");
+
+            let mut days: Vec<_> = days.into_iter().collect();
+            if self.direction == Direction::Backward {
+                days.reverse();
+            }
+
+            for (day, entries) in days {
+                if self.shutdown.is_triggered() {
+                    info!(%day, "Backfill stopped");
+                    break;
+                }
+
+                self.crawl_date = day;
+                let crawler = &*self;
+                async move {
+                    let status = crawler.day_status().await;
+                    if status == Some(CrawlStatus::Complete) {
+                        info!("Skip day, already complete");
+                        return;
+                    }
+
+                    crawler.start_day().await;
+                    crawler.crawl_day(entries, batch, status).await;
+                }.instrument(info_span!("day", crawl_date = %day)).await;
+            }
+        }
+
+        self.failed_days.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+use tokio::time;
+use tracing::warn;
+
+use crate::database::{db, rate_budget};
+
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// `requests` split into pieces of at most `limit`, which add up to all of them.
+fn pieces(requests: usize, limit: i32) -> impl Iterator<Item = i32> {
+    let mut left = requests as i32;
+
+    std::iter::from_fn(move || {
+        let piece = left.min(limit);
+        left -= piece;
+        (piece > 0).then_some(piece)
+    })
+}
+
+/// Requests per second shared by every worker of a fleet through the database, so
+/// running more workers never takes the fleet over the SEC's limit.
+#[derive(Clone)]
+pub struct RateBudget {
+    limit: i32,
+}
+
+impl RateBudget {
+    pub fn new(limit: u8) -> RateBudget {
+        RateBudget { limit: limit as i32 }
+    }
+
+    /// Waits until `requests` fit into the budget, taking them in pieces of at
+    /// most the limit so a batch larger than the limit spans several seconds.
+    /// When the database can't be reached it gives up after a second and
+    /// leaves the worker's own rate limit in charge.
+    pub async fn acquire(&self, requests: usize) {
+        for piece in pieces(requests, self.limit) {
+            if !self.acquire_piece(piece).await {
+                return;
+            }
+        }
+    }
+
+    /// Takes `requests` of at most the limit, false when the database failed.
+    async fn acquire_piece(&self, requests: i32) -> bool {
+        loop {
+            let limit = self.limit;
+            let taken = db()
+                .run(move |conn| rate_budget::take_requests(conn, requests, limit))
+                .await;
+
+            match taken {
+                Ok(true) => return true,
+                Ok(false) => time::sleep(RETRY_DELAY).await,
+                Err(err) => {
+                    warn!(%err, "Could not take from request budget");
+                    time::sleep(Duration::from_secs(1)).await;
+                    return false;
+                }
+            }
+        }
+    }
+
+    pub async fn prune(&self) {
+        let pruned = db().run(rate_budget::prune).await;
+
+        if let Err(err) = pruned {
+            warn!(%err, "Could not prune request budget");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_over_the_limit_are_taken_in_pieces() {
+        // a batch of 25 used to be counted as 10, under-counting the budget
+        assert_eq!(pieces(25, 10).collect::<Vec<_>>(), [10, 10, 5]);
+        assert_eq!(pieces(10, 10).collect::<Vec<_>>(), [10]);
+        assert_eq!(pieces(3, 10).collect::<Vec<_>>(), [3]);
+        assert_eq!(pieces(0, 10).count(), 0);
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    archive::SharedArchive,
+    secweb::Edgar,
+    database::{db, SqlHelper}};
+
+use super::pipeline::{Pipeline, PipelineStats};
+
+/// Crawls the whole insider history of one issuer or reporting owner from its
+/// submissions document instead of walking every daily index.
+pub struct CompanyCrawler {
+    edgar: Edgar,
+    archive: SharedArchive,
+    form_types: Vec<String>,
+    batch: usize,
+}
+
+impl CompanyCrawler {
+    pub fn new(edgar: Edgar, archive: SharedArchive, form_types: &[String], batch: usize) -> CompanyCrawler {
+        CompanyCrawler {
+            edgar,
+            archive,
+            form_types: form_types.to_vec(),
+            batch,
+        }
+    }
+
+    #[instrument(name = "company", skip_all, fields(%cik))]
+    pub async fn run(&self, cik: &str) -> Result<PipelineStats, String> {
+        let (submissions, entries) = match self.edgar.get_filer_entries(cik, &self.form_types).await {
+            Ok(Some(found)) => found,
+            Ok(None) => return Err("EDGAR has no submissions for CIK".to_string()),
+            Err(err) => return Err(format!("Error reading submissions: {err}")),
+        };
+
+        let access_nos: Vec<_> = entries.iter().map(|e| e.access_no()).collect();
+        let known = db().run(move |conn| SqlHelper::known_access_nos(conn, &access_nos)).await.unwrap_or_else(|err| {
+            warn!(%err, "Could not check submissions against form table");
+            HashSet::new()
+        });
+
+        let new_entries: Vec<_> = entries.into_iter()
+            .filter(|e| !known.contains(&e.access_no()))
+            .collect();
+
+        info!(name = %submissions.name, new = new_entries.len(), stored = known.len(), "Found filings");
+
+        // no JSON file here, the per day files are only written by full day crawls
+        let stats = Pipeline::new(self.edgar.clone(), self.batch)
+            .with_archive(self.archive.clone())
+            .run(new_entries)
+            .await;
+
+        // after the crawl so the issuer row exists for a first time company
+        let saved = db().run(move |conn| SqlHelper::save_issuer_metadata(conn, &submissions)).await;
+
+        match saved {
+            Ok(0) => (),
+            Ok(rows) => info!(rows, "Updated issuer metadata"),
+            Err(err) => warn!(%err, "Could not save metadata"),
+        }
+
+        Ok(stats)
+    }
+}
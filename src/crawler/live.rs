@@ -0,0 +1,143 @@
+use std::{collections::{HashMap, HashSet}, time::Duration};
+use tokio::time;
+use tracing::{error, info, warn};
+
+use crate::{
+    archive::SharedArchive,
+    secweb::{feed::FeedEntry, Edgar},
+    database::{db, SqlHelper}};
+
+use super::{pipeline::Pipeline, shutdown::Shutdown};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Polls a filing may fail in before it is left to the daily crawl
+const MAX_POLL_FAILURES: i32 = 3;
+
+/// Polls the EDGAR current events feed and sends filings that aren't in the
+/// database yet through the normal pipeline, so trades show up within seconds
+/// of being published instead of the next day.
+pub struct LiveCrawler {
+    edgar: Edgar,
+    archive: SharedArchive,
+    form_types: Vec<String>,
+    batch: usize,
+    // accession numbers stored this session, including filings without rows,
+    // while they're still on the feed
+    seen: HashSet<String>,
+    // failed polls of filings still tried again
+    failures: HashMap<String, i32>,
+    // filings that failed every poll they were tried in
+    given_up: usize,
+    shutdown: Shutdown,
+}
+
+impl LiveCrawler {
+    pub fn new(edgar: Edgar, archive: SharedArchive, form_types: &[String], batch: usize) -> LiveCrawler {
+        LiveCrawler {
+            edgar,
+            archive,
+            form_types: form_types.to_vec(),
+            batch,
+            seen: HashSet::new(),
+            failures: HashMap::new(),
+            given_up: 0,
+            shutdown: Shutdown::never(),
+        }
+    }
+
+    /// Stop polling on shutdown once the filings in flight are stored.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> LiveCrawler {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Polls until shutdown, returning how many filings it gave up on.
+    pub async fn run(&mut self) -> usize {
+        let mut ticker = time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        while !self.shutdown.is_triggered() {
+            tokio::select! {
+                _ = ticker.tick() => self.poll().await,
+                _ = self.shutdown.wait() => (),
+            }
+        }
+
+        info!(given_up = self.given_up, "Live crawl stopped");
+        self.given_up
+    }
+
+    pub async fn poll(&mut self) {
+        let mut entries = Vec::<FeedEntry>::new();
+        let mut complete = true;
+        for form_type in &self.form_types {
+            match self.edgar.get_current(form_type).await {
+                Ok(mut current) => entries.append(&mut current),
+                Err(err) => {
+                    error!(%form_type, %err, "Error reading current feed");
+                    complete = false;
+                }
+            }
+        }
+
+        // filings that dropped off the feed won't be listed again
+        if complete {
+            let listed: HashSet<_> = entries.iter().map(|e| &e.access_no).collect();
+            self.seen.retain(|access_no| listed.contains(access_no));
+            self.failures.retain(|access_no, _| listed.contains(access_no));
+        }
+
+        entries.retain(|e| !self.seen.contains(&e.access_no));
+        if entries.is_empty() {
+            return;
+        }
+
+        let access_nos: Vec<_> = entries.iter().map(|e| e.access_no.clone()).collect();
+        let known = db().run(move |conn| SqlHelper::known_access_nos(conn, &access_nos)).await;
+        let known = match known {
+            Ok(known) => known,
+            Err(err) => {
+                warn!(%err, "Could not check feed entries against form table");
+                return;
+            }
+        };
+
+        let (known, new): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| known.contains(&e.access_no));
+        self.seen.extend(known.into_iter().map(|e| e.access_no));
+        if new.is_empty() {
+            return;
+        }
+
+        info!(new = new.len(), "New filings on the current feed");
+        let stats = Pipeline::new(self.edgar.clone(), self.batch)
+            .with_archive(self.archive.clone())
+            .with_shutdown(self.shutdown.clone())
+            .run(new.iter().map(FeedEntry::index_entry).collect())
+            .await;
+
+        // failed filings are tried again on the next polls, and those a
+        // shutdown cut off aren't known to have been stored
+        if !stats.interrupted {
+            for access_no in new.into_iter().map(|e| e.access_no) {
+                if !stats.failed_filings.contains(&access_no) {
+                    self.failures.remove(&access_no);
+                    self.seen.insert(access_no);
+                    continue;
+                }
+
+                let failures = self.failures.entry(access_no.clone()).or_default();
+                *failures += 1;
+                if *failures >= MAX_POLL_FAILURES {
+                    warn!(%access_no, failures, "Giving up on filing for this session");
+                    self.given_up += 1;
+                    self.failures.remove(&access_no);
+                    self.seen.insert(access_no);
+                }
+            }
+        }
+
+        info!(
+            fetched = stats.fetched, parsed = stats.parsed, inserted = stats.inserted, failed = stats.failed,
+            "Live batch done");
+    }
+}
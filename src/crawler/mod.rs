@@ -1,25 +1,333 @@
+pub mod backfill;
+pub mod budget;
+pub mod company;
+pub mod live;
+pub mod pipeline;
+pub mod reparse;
+pub mod retry;
+pub mod shutdown;
+pub mod sink;
+pub mod tarball;
+pub mod worker;
+
 use std::{
+    collections::HashSet,
     fs::{self, File}, 
-    sync::{Arc, Mutex, }, 
-    thread::{sleep}, 
+    sync::atomic::{AtomicUsize, Ordering}, 
     time::Duration, 
-    ops::AddAssign, 
     path::Path, 
     io::BufReader};
 use serde_json::Result;
-use chrono::{NaiveDate, Days, Datelike};
+use chrono::{DateTime, NaiveDate, NaiveTime, Days, Datelike, TimeZone, Utc};
 use chrono_tz::{US::Eastern};
-use futures::*;
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    archive::{self, Archive, SharedArchive},
+    config::config,
+    database::{crawl_state::{self, CrawlStatus, DayCounts, MAX_ATTEMPTS}, db, DbConnection},
+    metrics::{self, count_index},
+    secweb::{models::FilingTransaction, calendar::is_business_day, daily_index_name, parse_daily_index, Edgar, IndexEntry}};
+
+use self::{
+    budget::RateBudget,
+    pipeline::{Pipeline, PipelineStats},
+    shutdown::Shutdown,
+    sink::{day_file_sink, BulkSink, DatabaseSink, FilingSink, StdoutSink}};
+
+const INDEX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Eastern time by which EDGAR has usually published a day's index
+const INDEX_PUBLISHED_HOUR: u32 = 22;
+/// Hours past that a missing index still counts as late rather than absent
+const INDEX_LATE_HOURS: i64 = 12;
+const INDEX_POLL_DELAY: Duration = Duration::from_secs(15 * 60);
+
+/// What came of crawling a day from its daily index.
+enum IndexOutcome {
+    /// Recorded as complete or failed, move on
+    Done,
+    /// The day was left for later, try again after the delay
+    RetryAfter(Duration),
+}
+
+/// The daily index lists a filing once per filer, a Form 4 under both its
+/// issuer and its owner. Keeps the first entry of each accession number.
+fn unique_entries(entries: Vec<IndexEntry>) -> Vec<IndexEntry> {
+    let mut seen = HashSet::new();
+    entries.into_iter()
+        .filter(|entry| seen.insert(entry.access_no()))
+        .collect()
+}
+
+/// Where parsed filings end up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sink {
+    /// The database `database.url` points at, Postgres or SQLite
+    #[value(alias = "postgres", alias = "sqlite")]
+    #[serde(alias = "postgres", alias = "sqlite")]
+    Database,
+    /// filings/YYYY/MM/YYYYMMDD-filing.json per crawled day
+    Json,
+    /// Same layout with one transaction per line
+    Ndjson,
+    /// Same layout as CSV with a header row
+    Csv,
+    /// One transaction per line on standard output
+    Stdout,
+}
 
-use crate::{secweb::{models::FilingTransaction, process_entries, get_daily_entries}, database::{get_connection_pool, SqlHelper}};
+impl Sink {
+    /// Extension of the day file, `None` for sinks without one.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Sink::Json => Some("json"),
+            Sink::Ndjson => Some("ndjson"),
+            Sink::Csv => Some("csv"),
+            Sink::Database | Sink::Stdout => None,
+        }
+    }
+}
+
+/// Order days are crawled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Direction {
+    /// Oldest day first
+    Forward,
+    /// Newest day first, so recent data is usable sooner
+    Backward,
+}
 
 pub struct Crawler {
     pub crawl_date: NaiveDate,
+    from: NaiveDate,
+    to: Option<NaiveDate>,
+    direction: Direction,
+    edgar: Edgar,
+    archive: SharedArchive,
+    form_types: Vec<String>,
+    sinks: Vec<Sink>,
+    /// Database rows go through `BulkSink`
+    bulk: bool,
+    budget: Option<RateBudget>,
+    shutdown: Shutdown,
+    /// Days recorded as failed by this crawler
+    failed_days: AtomicUsize,
+    /// Worker holding the lease on the crawl date, days are only recorded
+    /// while it does
+    leased_by: Option<String>,
 }
 
 impl Crawler {
+    /// Form types and sinks default to the config.
     pub fn new(start: &NaiveDate) -> Crawler {
-        Crawler { crawl_date: *start }
+        let config = config();
+        let archive = Archive::open(&config.storage.archive_dir)
+            .expect("Failed to open raw filing archive");
+
+        Crawler {
+            crawl_date: *start,
+            from: *start,
+            to: None,
+            direction: Direction::Forward,
+            edgar: Edgar::from_config(),
+            archive: archive.shared(),
+            form_types: config.edgar.form_types.clone(),
+            sinks: config.crawl.sinks.clone(),
+            bulk: false,
+            budget: None,
+            shutdown: Shutdown::never(),
+            failed_days: AtomicUsize::new(0),
+            leased_by: None,
+        }
+    }
+
+    /// Crawl `from..=to` in `direction`. Going forward without `to` follows new
+    /// days as they are published, going backward it starts from yesterday.
+    pub fn with_range(mut self, from: NaiveDate, to: Option<NaiveDate>, direction: Direction) -> Crawler {
+        self.from = from;
+        self.to = to;
+        self.direction = direction;
+        self.crawl_date = match direction {
+            Direction::Forward => from,
+            Direction::Backward => to.unwrap_or_else(Self::yesterday).min(Self::yesterday()),
+        };
+
+        self
+    }
+
+    /// Form types taken from the indexes.
+    pub fn with_form_types(mut self, form_types: &[String]) -> Crawler {
+        self.form_types = form_types.to_vec();
+        self
+    }
+
+    pub fn with_sinks(mut self, sinks: &[Sink]) -> Crawler {
+        self.sinks = sinks.to_vec();
+        self
+    }
+
+    /// Load database rows with `COPY` in batches of `BULK_ROWS` transactions
+    /// instead of a transaction per filing, for backfills.
+    pub fn with_bulk_load(mut self, bulk: bool) -> Crawler {
+        self.bulk = bulk;
+        self
+    }
+
+    /// Take every EDGAR request from a budget shared with other workers.
+    pub fn with_rate_budget(mut self, budget: RateBudget) -> Crawler {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Stop after the day in flight on shutdown, checkpointing it.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Crawler {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Picks up a forward crawl after the last day completed by an earlier run,
+    /// or at the oldest day before it that failed fewer than `MAX_ATTEMPTS`
+    /// times. Complete days in between are skipped, and only the entries of a
+    /// failed day that weren't persisted are fetched again.
+    pub async fn resume(mut self) -> Crawler {
+        if self.direction == Direction::Backward {
+            return self;
+        }
+
+        let Some(Some(mut date)) = self.with_state(crawl_state::resume_date).await else {
+            return self;
+        };
+
+        let last = date;
+        let days = self.with_state(move |conn| crawl_state::unfinished_before(conn, last)).await.unwrap_or_default();
+        for (day, attempts) in days {
+            match attempts < MAX_ATTEMPTS {
+                true => date = date.min(day),
+                false => warn!(%day, attempts, "Day keeps failing, recrawl it with --from {day} --to {day}"),
+            }
+        }
+
+        info!(%date, "Resuming crawl");
+        self.from = date;
+        self.crawl_date = date;
+        self
+    }
+
+    /// Runs `f` on one of the database threads. `None` when the crawl
+    /// does not write to the database or the state could not be read or written,
+    /// which is logged but never stops the crawl.
+    async fn with_state<T, F>(&self, f: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut DbConnection) -> diesel::QueryResult<T> + Send + 'static,
+    {
+        if !self.sinks.contains(&Sink::Database) {
+            return None;
+        }
+
+        match db().run(f).await {
+            Ok(value) => Some(value),
+            Err(err) => {
+                error!(%err, "Error updating crawl state");
+                None
+            }
+        }
+    }
+
+    async fn day_status(&self) -> Option<CrawlStatus> {
+        let date = self.crawl_date;
+        self.with_state(move |conn| crawl_state::day_status(conn, date)).await.flatten()
+    }
+
+    async fn start_day(&self) {
+        let date = self.crawl_date;
+        self.with_state(move |conn| crawl_state::start_day(conn, date)).await;
+    }
+
+    async fn finish_day(&self, status: CrawlStatus, counts: DayCounts, error: Option<String>) {
+        match status {
+            CrawlStatus::Complete => metrics::metrics().last_day_completed.set(Utc::now().timestamp()),
+            CrawlStatus::Failed => {
+                self.failed_days.fetch_add(1, Ordering::Relaxed);
+            },
+            _ => (),
+        }
+
+        let date = self.crawl_date;
+        let leased_by = self.leased_by.clone();
+        self.with_state(move |conn| crawl_state::finish_day(conn, date, status, counts, error.as_deref(), leased_by.as_deref())).await;
+    }
+
+    /// Registers the business days of a bounded range as pending, which is
+    /// also how work is handed to workers. Returns how many days were new.
+    pub async fn plan_range(&self) -> usize {
+        let Some(to) = self.to else {
+            return 0;
+        };
+
+        let days: Vec<NaiveDate> = self.from.iter_days()
+            .take_while(|day| *day <= to.min(Self::yesterday()))
+            .filter(|day| is_business_day(*day))
+            .collect();
+
+        if days.is_empty() {
+            return 0;
+        }
+
+        self.with_state(move |conn| crawl_state::plan_days(conn, &days))
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Crawls one day's entries, or inserts them from its JSON file when an
+    /// earlier run saved the whole day, and records the outcome. The day must
+    /// already be started or leased.
+    async fn crawl_day(&self, entries: Vec<IndexEntry>, batch: usize, status: Option<CrawlStatus>) {
+        let entries = unique_entries(entries);
+        let total = entries.len();
+
+        // a day that never completed may have left a partial JSON file behind
+        let resumed = matches!(status, Some(CrawlStatus::InProgress | CrawlStatus::Failed));
+        let stats = match resumed {
+            false => self.insert_saved_day().await,
+            true => None,
+        };
+
+        let stats = match stats {
+            Some(stats) => stats,
+            None => self.crawl_entries(entries, batch).await,
+        };
+
+        let counts = DayCounts {
+            entries: total,
+            fetched: stats.fetched,
+            parsed: stats.parsed,
+            inserted: stats.inserted,
+            failed: stats.failed,
+        };
+        let (status, error) = Self::day_outcome(&stats);
+        self.finish_day(status, counts, error).await;
+    }
+
+    /// Stored entries are already marked, the rest is picked up when the day
+    /// is tried again: later by a resumed crawl or worker after failures, or
+    /// as soon as possible after a shutdown.
+    fn day_outcome(stats: &PipelineStats) -> (CrawlStatus, Option<String>) {
+        match (stats.interrupted, stats.failed) {
+            (true, _) => (CrawlStatus::Pending, Some("Interrupted by shutdown".to_string())),
+            (false, 0) => (CrawlStatus::Complete, None),
+            (false, failed) => (CrawlStatus::Failed, Some(format!("{failed} failed, see crawl_entry and failed.txt"))),
+        }
+    }
+
+    /// When EDGAR's end-of-day index for `date` should be out.
+    fn index_ready_at(date: NaiveDate) -> DateTime<Utc> {
+        let published = date.and_time(NaiveTime::from_hms_opt(INDEX_PUBLISHED_HOUR, 0, 0).unwrap());
+        Eastern.from_local_datetime(&published)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc)
     }
 
     fn yesterday() -> NaiveDate {
@@ -30,141 +338,312 @@ impl Crawler {
             .unwrap()
     }
 
-    fn increment_day(&mut self) {
-        self.crawl_date = self.crawl_date
-            .checked_add_days(Days::new(1))
-            .unwrap();
+    fn next_day(&mut self) {
+        self.crawl_date = match self.direction {
+            Direction::Forward => self.crawl_date.checked_add_days(Days::new(1)),
+            Direction::Backward => self.crawl_date.checked_sub_days(Days::new(1)),
+        }.unwrap();
+    }
+
+    /// Whether the crawl date has left the range.
+    pub fn finished(&self) -> bool {
+        match self.direction {
+            Direction::Forward => self.to.is_some_and(|to| self.crawl_date > to),
+            Direction::Backward => self.crawl_date < self.from,
+        }
     }
 
     fn get_save_dir(date: NaiveDate) -> String {
         let year = date.year();
         let month = date.format("%m");
-        format!("filings/{year}/{month}")
+        format!("{}/{year}/{month}", config().storage.filings_dir.display())
     }
 
-    fn get_file_path(&self) -> String {
+    /// Day file path without the extension of its sink.
+    fn get_file_stem(&self) -> String {
         let date = self.crawl_date.format("%Y%m%d");
-        format!("{}/{date}-filing.json", Self::get_save_dir(self.crawl_date))
+        format!("{}/{date}-filing", Self::get_save_dir(self.crawl_date))
+    }
+
+    fn get_file_path(&self) -> String {
+        format!("{}.json", self.get_file_stem())
+    }
+
+    /// Raw daily index for the crawl date, read from the archive when it was
+    /// fetched before and archived otherwise. `None` when EDGAR has no index.
+    async fn get_daily_index(&self) -> std::result::Result<Option<String>, reqwest::Error> {
+        let name = daily_index_name(self.crawl_date);
+        let key = name.clone();
+        let archived = archive::run(&self.archive, move |archive| archive.get(&key)).await;
+        if let Ok(Some(body)) = archived {
+            info!(%name, "Using archived index");
+            count_index("daily", "archived");
+            return Ok(Some(body));
+        }
+
+        if let Some(budget) = &self.budget {
+            budget.acquire(1).await;
+        }
+
+        let fetched = self.edgar.fetch_daily_index(self.crawl_date).await;
+        count_index("daily", match &fetched {
+            Ok(Some(_)) => "ok",
+            Ok(None) => "missing",
+            Err(_) => "error",
+        });
+
+        let Some(body) = fetched? else {
+            return Ok(None);
+        };
+
+        let (key, date) = (name.clone(), self.crawl_date);
+        let (stored, body) = archive::run(&self.archive, move |archive| {
+            (archive.put_daily_index(&key, date, &body), body)
+        }).await;
+        if let Err(err) = stored {
+            warn!(%name, %err, "Could not archive index");
+        }
+
+        Ok(Some(body))
+    }
+
+    /// Inserts the crawl date from its JSON file when an earlier run finished
+    /// that day, `None` when there was nothing to insert. Without the database the
+    /// day is done once every day file is in place.
+    async fn insert_saved_day(&self) -> Option<PipelineStats> {
+        if !self.sinks.contains(&Sink::Database) {
+            let stem = self.get_file_stem();
+            let mut paths = self.sinks.iter().map(|sink| sink.extension().map(|ext| format!("{stem}.{ext}")));
+            if paths.all(|path| path.is_some_and(|path| Path::new(&path).exists())) {
+                info!(%stem, "Skipping day, already saved");
+                return Some(PipelineStats::default());
+            }
+
+            return None;
+        }
+
+        let path = self.get_file_path();
+        if !Path::new(&path).exists() {
+            return None;
+        }
+
+        let file = File::open(&path);
+        let rdr = BufReader::new(file.unwrap());
+
+        let filings: Result<Vec<FilingTransaction>> = serde_json::from_reader(rdr);
+        match filings {
+            Ok(filings) => {
+                info!(%path, "Inserting from previously saved file");
+                let stats = Pipeline::save(filings).await;
+                info!(inserted = stats.inserted, failed = stats.failed, "Inserted saved day");
+                Some(stats)
+            },
+            Err(_) => None,
+        }
+    }
+
+    fn database_sink(&self, tracked: bool) -> Box<dyn FilingSink> {
+        match (self.bulk, tracked) {
+            (true, true) => Box::new(BulkSink::new().with_entry_tracking()),
+            (true, false) => Box::new(BulkSink::new()),
+            (false, true) => Box::new(DatabaseSink::new().with_entry_tracking()),
+            (false, false) => Box::new(DatabaseSink::new()),
+        }
     }
 
-    fn save_filings_json(&self, filings: &[FilingTransaction]) {
-        let filepath = self.get_file_path();
-        
+    /// Runs one day's index entries through the pipeline. Entries an earlier
+    /// run already stored are not fetched again, their archived documents
+    /// only feed the day files.
+    async fn crawl_entries(&self, entries: Vec<IndexEntry>, batch: usize) -> PipelineStats {
         fs::create_dir_all(Self::get_save_dir(self.crawl_date))
             .expect("Failed to create dir path");
-        
-        let text = serde_json::to_string(&filings).expect("Failed to serialize struct");
-        fs::write(filepath, text).expect("Unable to write file");
-    }
-
-    async fn save_filings_db(filings: &[FilingTransaction]) -> Result<()> {
-        let pool = get_connection_pool();
-        let helper = Arc::new(Mutex::new(SqlHelper::new()));
-        
-        let total = filings.len();
-        let i = Arc::new(Mutex::new(0));
-        let stream = stream::iter(filings);
-
-        stream
-            .for_each_concurrent(10, |trans| async {
-                let conn = &mut pool.get().unwrap();
-                let mut helper = helper.lock().unwrap();
-                let issuer = helper.create_issuer(conn, trans).ok();
-                let ind = helper.create_individual(conn, trans).ok();
-                
-                if ind.is_none() || issuer.is_none() {
-                    let mut progress = i.lock().unwrap();
-                    progress.add_assign(1);
-                    println!("failed insert {progress}/{total}");
-                }
-                
-                let form_id = helper.create_form(conn, trans, issuer.unwrap());
-                if form_id.is_ok() {
-                    let form_id = form_id.unwrap();
-                    let result = SqlHelper::insert_nonderiv(
-                        conn, 
-                        trans, 
-                        form_id, 
-                        issuer.unwrap(), 
-                        ind.unwrap());
-    
-                    if result.is_err() {
-                        println!("Error occurred adding transaction for form ID: {}", form_id);
+
+        let mut pipeline = Pipeline::new(self.edgar.clone(), batch)
+            .with_archive(self.archive.clone());
+
+        let stem = self.get_file_stem();
+        for sink in &self.sinks {
+            match sink {
+                Sink::Database => (),
+                Sink::Stdout => pipeline = pipeline.with_sink(Box::new(StdoutSink::new())),
+                _ => pipeline = pipeline.with_sink(day_file_sink(*sink, &stem).unwrap()),
+            }
+        }
+
+        if let Some(budget) = &self.budget {
+            pipeline = pipeline.with_rate_budget(budget.clone());
+        }
+
+        pipeline = pipeline.with_shutdown(self.shutdown.clone());
+
+        let date = self.crawl_date;
+        let planned = entries.clone();
+        let done = self.with_state(move |conn| {
+            crawl_state::plan_entries(conn, date, &planned)?;
+            crawl_state::persisted_entries(conn, date)
+        }).await;
+
+        let mut persisted = Vec::new();
+        let mut entries = entries;
+        if let Some(done) = done {
+            pipeline = pipeline.with_sink(self.database_sink(true));
+
+            let (stored, remaining): (Vec<_>, Vec<_>) = entries.into_iter()
+                .partition(|entry| done.contains(&entry.access_no()));
+            entries = remaining;
+
+            if !stored.is_empty() {
+                info!(stored = stored.len(), left = entries.len(), "Resuming day");
+            }
+
+            if self.sinks.iter().any(|sink| sink.extension().is_some()) {
+                let bodies = archive::run(&self.archive, move |archive| stored.into_iter()
+                    .map(|entry| {
+                        let body = archive.get(&entry.access_no());
+                        (entry, body)
+                    })
+                    .collect::<Vec<_>>()).await;
+
+                for (entry, body) in bodies {
+                    match body {
+                        Ok(Some(body)) => persisted.push((entry, body)),
+                        // without the raw document the day files need it fetched again
+                        _ => entries.push(entry),
                     }
                 }
+            }
+        } else if self.sinks.contains(&Sink::Database) {
+            // crawl state is unavailable, insert without tracking
+            pipeline = pipeline.with_sink(self.database_sink(false));
+        }
 
-                let mut progress = i.lock().unwrap();
-                progress.add_assign(1);
-                println!("insert {progress}/{total}");
-            }).await;
-        
-        Ok(())
+        let stats = pipeline.run_resumed(entries, persisted).await;
+
+        info!(
+            fetched = stats.fetched, parsed = stats.parsed, inserted = stats.inserted, failed = stats.failed,
+            "Finished day");
+
+        stats
     }
 
-    pub async fn run(&mut self, batch: usize) {
-        if batch > 10 {
-            panic!("Due to SEC limits, batch per second must be <= 10");
+    /// Crawls day by day until the range is done, which never happens going
+    /// forward without an end date. Returns how many days failed.
+    pub async fn crawl(&mut self, batch: usize) -> usize {
+        self.plan_range().await;
+
+        while !self.finished() && !self.shutdown.is_triggered() {
+            self.run(batch).await;
         }
 
-        if self.crawl_date > Self::yesterday() {
-            println!("{} is today... waiting for that to change", self.crawl_date);
-            
-            let min_delay = Duration::from_secs(60);
-            sleep(min_delay);
+        match self.shutdown.is_triggered() {
+            true => info!(crawl_date = %self.crawl_date, "Crawl stopped"),
+            false => info!(crawl_date = %self.crawl_date, "Stop date reached"),
+        }
+
+        self.failed_days.load(Ordering::Relaxed)
+    }
+
+    #[instrument(name = "day", skip_all, fields(crawl_date = %self.crawl_date))]
+    pub async fn run(&mut self, batch: usize) {
+        let lag = (Self::yesterday() - self.crawl_date).num_days().max(0);
+        metrics::metrics().crawl_lag_days.set(lag);
+
+        let ready_at = Self::index_ready_at(self.crawl_date);
+        if let Ok(wait) = (ready_at - Utc::now()).to_std() {
+            info!(ready_at = %ready_at.with_timezone(&Eastern), "Waiting for the daily index");
+            self.shutdown.sleep(wait).await;
+            return;
+        }
 
+        if !is_business_day(self.crawl_date) {
+            info!("Skip day, not a business day");
+            self.next_day();
             return;
         }
 
-        let db = Arc::new(Mutex::new(Vec::<FilingTransaction>::new()));
+        let status = self.day_status().await;
+        if status == Some(CrawlStatus::Complete) {
+            info!("Skip day, already complete");
+            self.next_day();
+            return;
+        }
 
-        // check for json file saved previously
-        let path = self.get_file_path();
-        let existing = Path::new(&path).exists();
-        if existing {
-            let file = File::open(&path);
-            let rdr = BufReader::new(file.unwrap());
-
-            let filings: Result<Vec<FilingTransaction>> = serde_json::from_reader(rdr);
-            if filings.is_ok() {
-                println!("Inserting from previously saved file {path}");
-                Self::save_filings_db(&filings.unwrap())
-                    .await
-                    .expect("Error saving to db");
-                
-                self.increment_day();
+        // check for json file saved previously, no need for the index then
+        if status.is_none() || status == Some(CrawlStatus::Pending) {
+            if let Some(stats) = self.insert_saved_day().await {
+                self.start_day().await;
+                let counts = DayCounts { inserted: stats.inserted, failed: stats.failed, ..Default::default() };
+                let (status, error) = Self::day_outcome(&stats);
+                self.finish_day(status, counts, error).await;
+                self.next_day();
                 return;
             }
         }
 
-        let body = get_daily_entries(self.crawl_date).await.unwrap();
-        
-        if body.len() == 0 {
-            println!("Skip day {} index empty", self.crawl_date);
-            self.increment_day();
-            return;
+        self.start_day().await;
+        match self.crawl_from_index(batch, status).await {
+            IndexOutcome::Done if !self.shutdown.is_triggered() => self.next_day(),
+            IndexOutcome::Done => (),
+            IndexOutcome::RetryAfter(delay) => {
+                self.shutdown.sleep(delay).await;
+            }
         }
+    }
 
-        let second_delay = Duration::from_secs(1);
-        
-        let mut skip = 0;
-        let total = body.len() / batch;
-        for i in 0..total {
-            println!("Get {i}/{total}");
-
-            process_entries(&body, db.clone(), skip, batch).await.unwrap();
-            
-            skip += batch;
+    /// Crawls the crawl date from its daily index, which must already be
+    /// started or leased. A failed index request is recorded as failed and a
+    /// late index leaves the day pending, both to be tried again later.
+    async fn crawl_from_index(&self, batch: usize, status: Option<CrawlStatus>) -> IndexOutcome {
+        let index = match self.get_daily_index().await {
+            Ok(Some(index)) => index,
+            Ok(None) if Utc::now() < Self::index_ready_at(self.crawl_date) + chrono::Duration::hours(INDEX_LATE_HOURS) => {
+                info!("Daily index is not published yet");
+                let error = Some("Daily index not published yet".to_string());
+                self.finish_day(CrawlStatus::Pending, DayCounts::default(), error).await;
+                return IndexOutcome::RetryAfter(INDEX_POLL_DELAY);
+            },
+            Ok(None) => {
+                let error = format!("EDGAR has no daily index for business day {}", self.crawl_date);
+                error!("{error}");
+                self.finish_day(CrawlStatus::Failed, DayCounts::default(), Some(error)).await;
+                return IndexOutcome::Done;
+            },
+            Err(err) => {
+                error!(%err, "Error fetching daily index");
+                self.finish_day(CrawlStatus::Failed, DayCounts::default(), Some(err.to_string())).await;
+                return IndexOutcome::RetryAfter(INDEX_RETRY_DELAY);
+            }
+        };
 
-            // avoid SEC rate limiting by sleeping for 1 sec
-            sleep(second_delay);
+        let body = parse_daily_index(&index, &self.form_types);
+        if body.is_empty() {
+            info!("Skip day, no matching filings");
+            self.finish_day(CrawlStatus::Complete, DayCounts::default(), None).await;
+            return IndexOutcome::Done;
         }
-    
-        let filings = db.lock().unwrap();
-        
-        self.save_filings_json(&filings);
 
-        Self::save_filings_db(&filings).await.expect("Should have saved to local file and db");
-        
-        self.increment_day();
+        self.crawl_day(body, batch, status).await;
+        IndexOutcome::Done
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/edgar/Archives/edgar/full-index/2023/QTR1/master.idx"));
+
+    #[test]
+    fn filings_listed_per_filer_are_crawled_once() {
+        // the form 4 appears under Apple and under Tim Cook
+        let entries = parse_daily_index(FULL, &["4".to_string(), "8-K".to_string()]);
+        assert_eq!(entries.len(), 3);
+
+        let entries = unique_entries(entries);
+        let found: Vec<_> = entries.iter().map(|entry| (entry.company_cik.as_str(), entry.access_no())).collect();
+        assert_eq!(found, [
+            ("320193", "0001209191-23-004512".to_string()),
+            ("320193", "0000320193-23-000005".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant}};
+use futures::future::join_all;
+use tokio::{sync::{mpsc, Mutex}, task, time};
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
+
+use crate::{
+    archive::SharedArchive,
+    metrics,
+    secweb::{models::FilingTransaction, form_url, save_failed, Edgar, FilingDoc, IndexEntry}};
+
+use super::{budget::RateBudget, shutdown::Shutdown, sink::{FilingSink, DatabaseSink, Loaded}};
+
+const PARSE_WORKERS: usize = 4;
+const PARSED_BUFFER: usize = 64;
+
+/// A document on its way to the parsers. Fetch errors travel along so the
+/// writer can record them, and `persisted` documents were stored by an
+/// earlier run and only go to sinks that replay them.
+struct FetchedDoc {
+    entry: IndexEntry,
+    body: Result<String, String>,
+    persisted: bool,
+    span: Span,
+}
+
+impl FetchedDoc {
+    fn new(entry: IndexEntry, body: Result<String, String>, persisted: bool) -> FetchedDoc {
+        let span = filing_span(&entry);
+        FetchedDoc { entry, body, persisted, span }
+    }
+}
+
+#[derive(Debug)]
+struct ParsedDoc {
+    access_no: String,
+    filings: Result<Vec<FilingTransaction>, String>,
+    persisted: bool,
+    span: Span,
+}
+
+/// Follows one filing through every stage. The CIKs are filled in once the
+/// document is parsed.
+fn filing_span(entry: &IndexEntry) -> Span {
+    info_span!("filing", access_no = %entry.access_no(), issuer_cik = field::Empty, owner_cik = field::Empty)
+}
+
+/// Splits transactions of several filings into one group per accession
+/// number, in the order the filings first appear.
+fn group_by_access_no(filings: Vec<FilingTransaction>) -> Vec<(String, Vec<FilingTransaction>)> {
+    let mut groups: Vec<(String, Vec<FilingTransaction>)> = Vec::new();
+    let mut index = HashMap::new();
+
+    for trans in filings {
+        let i = *index.entry(trans.access_no.clone()).or_insert_with(|| {
+            groups.push((trans.access_no.clone(), Vec::new()));
+            groups.len() - 1
+        });
+        groups[i].1.push(trans);
+    }
+
+    groups
+}
+
+/// What the writer stage counts: transactions stored by every sink and those
+/// of filings any sink failed on. Filings a batching sink staged are counted
+/// once it reports their load.
+#[derive(Debug, Default)]
+struct Tally {
+    inserted: usize,
+    failed: usize,
+    /// Staged filings already counted as failed by another sink
+    failed_staged: HashSet<String>,
+    /// Accession numbers of filings that weren't stored everywhere
+    failed_filings: HashSet<String>,
+}
+
+impl Tally {
+    fn add_loaded(&mut self, loaded: Loaded) {
+        for (access_no, rows) in loaded.stored {
+            if !self.failed_staged.remove(&access_no) {
+                self.inserted += rows;
+                metrics::count_filing("stored");
+            }
+        }
+
+        for (access_no, rows) in loaded.failed {
+            if !self.failed_staged.remove(&access_no) {
+                self.failed += rows;
+            }
+            self.failed_filings.insert(access_no);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PipelineStats {
+    pub fetched: usize,
+    pub parsed: usize,
+    pub inserted: usize,
+    pub failed: usize,
+    /// Shutdown stopped the source before it ran out of documents
+    pub interrupted: bool,
+    /// Accession numbers of the filings that failed to fetch, parse or store
+    pub failed_filings: HashSet<String>,
+}
+
+/// Streams index entries through fetch -> parse -> persist stages connected by
+/// bounded channels, so a slow database applies backpressure all the way back
+/// to the fetcher and every filing is stored as soon as it is parsed.
+pub struct Pipeline {
+    edgar: Edgar,
+    batch: usize,
+    archive: Option<SharedArchive>,
+    /// The database alone when none are given
+    sinks: Vec<Box<dyn FilingSink>>,
+    budget: Option<RateBudget>,
+    shutdown: Shutdown,
+}
+
+impl Pipeline {
+    pub fn new(edgar: Edgar, batch: usize) -> Pipeline {
+        if batch == 0 || batch > 10 {
+            panic!("Due to SEC limits, batch per second must be between 1 and 10");
+        }
+
+        Pipeline { edgar, batch, archive: None, sinks: Vec::new(), budget: None, shutdown: Shutdown::never() }
+    }
+
+    /// Write parsed filings to `sink`, in addition to any sinks added before.
+    /// Without any the pipeline inserts into the database.
+    pub fn with_sink(mut self, sink: Box<dyn FilingSink>) -> Pipeline {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Keep every fetched document in the raw archive before parsing it.
+    pub fn with_archive(mut self, archive: SharedArchive) -> Pipeline {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Also take every request from a budget shared with other workers.
+    pub fn with_rate_budget(mut self, budget: RateBudget) -> Pipeline {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Stop taking new documents on shutdown and drain the ones in flight.
+    /// Files of an interrupted run are left as `.part`.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Pipeline {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub async fn run(self, entries: Vec<IndexEntry>) -> PipelineStats {
+        self.run_resumed(entries, Vec::new()).await
+    }
+
+    /// Like `run`, with documents of entries an earlier run already stored.
+    /// Those are parsed again for sinks that replay them but not fetched.
+    pub async fn run_resumed(self, entries: Vec<IndexEntry>, persisted: Vec<(IndexEntry, String)>) -> PipelineStats {
+        let edgar = self.edgar.clone();
+        let batch = self.batch;
+        let budget = self.budget.clone();
+        let shutdown = self.shutdown.clone();
+
+        self.run_from(|tx| async move {
+            for (entry, body) in persisted {
+                if tx.send(FetchedDoc::new(entry, Ok(body), true)).await.is_err() {
+                    return PipelineStats::default();
+                }
+            }
+
+            Self::fetch_stage(&edgar, entries, batch, budget, shutdown, tx).await
+        }).await
+    }
+
+    /// Parse and persist documents that are already on hand, e.g. read from a
+    /// feed tarball, so nothing is fetched and the rate limit doesn't apply.
+    pub async fn run_documents(self, mut docs: mpsc::Receiver<(IndexEntry, String)>) -> PipelineStats {
+        let shutdown = self.shutdown.clone();
+
+        self.run_from(|tx| async move {
+            let mut stats = PipelineStats::default();
+            while let Some((entry, body)) = docs.recv().await {
+                if shutdown.is_triggered() {
+                    stats.interrupted = true;
+                    break;
+                }
+
+                stats.fetched += 1;
+                if tx.send(FetchedDoc::new(entry, Ok(body), false)).await.is_err() {
+                    break;
+                }
+            }
+
+            stats
+        }).await
+    }
+
+    /// Wires the parse and write stages to a document source, which reports
+    /// how many documents it produced, how many it failed to get and whether
+    /// it was interrupted.
+    async fn run_from<F, Fut>(mut self, source: F) -> PipelineStats
+    where
+        F: FnOnce(mpsc::Sender<FetchedDoc>) -> Fut,
+        Fut: Future<Output = PipelineStats>,
+    {
+        let (doc_tx, doc_rx) = mpsc::channel::<FetchedDoc>(self.batch * 2);
+        let (parsed_tx, parsed_rx) = mpsc::channel::<ParsedDoc>(PARSED_BUFFER);
+
+        if self.sinks.is_empty() {
+            self.sinks.push(Box::new(DatabaseSink::new()));
+        }
+
+        let span = Span::current();
+        let sinks = self.sinks;
+        let writer = task::spawn_blocking({
+            let span = span.clone();
+            move || span.in_scope(|| Self::write_stage(parsed_rx, sinks))
+        });
+
+        let doc_rx = Arc::new(Mutex::new(doc_rx));
+        let parsers: Vec<_> = (0..PARSE_WORKERS)
+            .map(|_| tokio::spawn(
+                Self::parse_stage(doc_rx.clone(), parsed_tx.clone(), self.archive.clone()).in_current_span()))
+            .collect();
+        drop(parsed_tx);
+
+        let mut stats = source(doc_tx).await;
+        for (parsed, failed) in join_all(parsers).await.into_iter().flatten() {
+            stats.parsed += parsed;
+            stats.failed += failed;
+        }
+
+        let (mut tally, sinks) = writer.await.expect("Writer stage panicked");
+
+        // sinks may still load or rename files, keep that off the runtime too
+        let complete = !stats.interrupted;
+        let tally = task::spawn_blocking(move || span.in_scope(|| {
+            for sink in sinks {
+                tally.add_loaded(sink.finish(complete));
+            }
+            tally
+        })).await.expect("Sink panicked while finishing");
+
+        stats.inserted = tally.inserted;
+        stats.failed += tally.failed;
+        stats.failed_filings = tally.failed_filings;
+        stats
+    }
+
+    /// Persist already parsed transactions to the database through the same
+    /// writer stage, one filing per accession number, e.g. a day file's rows.
+    pub async fn save(filings: Vec<FilingTransaction>) -> PipelineStats {
+        let (tx, rx) = mpsc::channel(1);
+        let sinks: Vec<Box<dyn FilingSink>> = vec![Box::new(DatabaseSink::new())];
+        let writer = task::spawn_blocking(move || Self::write_stage(rx, sinks));
+
+        for (access_no, filings) in group_by_access_no(filings) {
+            let span = info_span!(
+                "filing", %access_no, issuer_cik = %filings[0].company_cik, owner_cik = %filings[0].owner_cik);
+            let doc = ParsedDoc { access_no, filings: Ok(filings), persisted: false, span };
+            tx.send(doc).await.expect("Writer stage closed early");
+        }
+        drop(tx);
+
+        let (tally, _) = writer.await.expect("Writer stage panicked");
+        PipelineStats {
+            inserted: tally.inserted,
+            failed: tally.failed,
+            failed_filings: tally.failed_filings,
+            ..Default::default()
+        }
+    }
+
+    async fn fetch_stage(
+        edgar: &Edgar,
+        entries: Vec<IndexEntry>,
+        batch: usize,
+        budget: Option<RateBudget>,
+        shutdown: Shutdown,
+        tx: mpsc::Sender<FetchedDoc>) -> PipelineStats
+    {
+        let mut stats = PipelineStats::default();
+        let total = entries.len();
+
+        // avoid SEC rate limiting by sending at most `batch` requests per second
+        let mut ticker = time::interval(Duration::from_secs(1));
+
+        for (i, chunk) in entries.chunks(batch).enumerate() {
+            let waiting = Instant::now();
+            ticker.tick().await;
+            metrics::observe_wait("local", waiting.elapsed());
+
+            if shutdown.is_triggered() {
+                info!(done = i * batch, total, "Stopped fetching");
+                stats.interrupted = true;
+                break;
+            }
+
+            if let Some(budget) = &budget {
+                let waiting = Instant::now();
+                budget.acquire(chunk.len()).await;
+                metrics::observe_wait("shared", waiting.elapsed());
+            }
+            info!(done = i * batch, total, "Fetching");
+
+            let spans: Vec<_> = chunk.iter().map(filing_span).collect();
+            let bodies = join_all(chunk.iter().zip(&spans)
+                .map(|(entry, span)| edgar.fetch_form(entry).instrument(span.clone()))).await;
+
+            for ((entry, span), body) in chunk.iter().zip(spans).zip(bodies) {
+                match body {
+                    Ok(body) => {
+                        stats.fetched += 1;
+                        metrics::count_filing("fetched");
+                        let doc = FetchedDoc { entry: entry.clone(), body: Ok(body), persisted: false, span };
+                        if tx.send(doc).await.is_err() {
+                            return stats;
+                        }
+                    },
+                    Err(err) => {
+                        stats.failed += 1;
+                        metrics::count_failure(&match err.status() {
+                            Some(status) => format!("fetch_http_{}", status.as_u16()),
+                            None => "fetch_network".to_string(),
+                        });
+                        span.in_scope(|| error!(path = %entry.filepath, %err, "Error fetching filing"));
+                        save_failed(entry);
+
+                        let doc = FetchedDoc { entry: entry.clone(), body: Err(err.to_string()), persisted: false, span };
+                        if tx.send(doc).await.is_err() {
+                            return stats;
+                        }
+                    }
+                }
+            }
+        }
+
+        stats
+    }
+
+    async fn parse_stage(
+        rx: Arc<Mutex<mpsc::Receiver<FetchedDoc>>>,
+        tx: mpsc::Sender<ParsedDoc>,
+        archive: Option<SharedArchive>) -> (usize, usize)
+    {
+        let mut parsed = 0;
+        let mut failed = 0;
+
+        loop {
+            let doc = rx.lock().await.recv().await;
+            let Some(FetchedDoc { entry, body, persisted, span }) = doc else {
+                break;
+            };
+
+            // already counted and recorded by the fetcher, only passed on
+            let body = match body {
+                Ok(body) => body,
+                Err(err) => {
+                    let doc = ParsedDoc { access_no: entry.access_no(), filings: Err(err), persisted, span };
+                    if tx.send(doc).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            let url = form_url(&entry);
+            let archive = archive.clone().filter(|_| !persisted);
+            let raw_entry = entry.clone();
+            let parse_span = span.clone();
+            let result = task::spawn_blocking(move || parse_span.in_scope(|| {
+                if let Some(archive) = archive {
+                    let stored = archive.lock().unwrap().put_filing(&raw_entry, &body);
+                    if let Err(err) = stored {
+                        warn!(path = %raw_entry.filepath, %err, "Could not archive filing");
+                    }
+                }
+
+                FilingDoc::new(&url, &body).map_err(|e| e.to_string())
+            })).await;
+
+            let filings = span.in_scope(|| match result {
+                Ok(Ok(filings)) => {
+                    if let Some(trans) = filings.first() {
+                        span.record("issuer_cik", field::display(&trans.company_cik));
+                        span.record("owner_cik", field::display(&trans.owner_cik));
+                    }
+
+                    if !persisted {
+                        parsed += 1;
+                        metrics::count_filing("parsed");
+                    }
+                    Ok(filings)
+                },
+                Ok(Err(err)) => {
+                    error!(path = %entry.filepath, %err, "Error parsing filing");
+                    Err(("parse", err))
+                },
+                Err(_) => {
+                    error!(path = %entry.filepath, "Parser panicked");
+                    Err(("parser_panic", "parser panicked".to_string()))
+                }
+            });
+
+            let filings = filings.map_err(|(class, err)| {
+                if !persisted {
+                    failed += 1;
+                    metrics::count_failure(class);
+                    save_failed(&entry);
+                }
+                err
+            });
+
+            let doc = ParsedDoc { access_no: entry.access_no(), filings, persisted, span };
+            if tx.send(doc).await.is_err() {
+                break;
+            }
+        }
+
+        (parsed, failed)
+    }
+
+    /// Hands every filing to each sink, counting the transactions stored by
+    /// all of them and those of filings any sink failed on.
+    fn write_stage(
+        mut rx: mpsc::Receiver<ParsedDoc>,
+        mut sinks: Vec<Box<dyn FilingSink>>) -> (Tally, Vec<Box<dyn FilingSink>>)
+    {
+        let mut tally = Tally::default();
+
+        while let Some(ParsedDoc { access_no, filings, persisted, span }) = rx.blocking_recv() {
+            let _entered = span.enter();
+            let targets = sinks.iter_mut().filter(|sink| !persisted || sink.replays_stored());
+
+            let filings = match filings {
+                Ok(filings) => filings,
+                Err(err) => {
+                    targets.for_each(|sink| sink.write_failed(&access_no, &err));
+                    if !persisted {
+                        tally.failed_filings.insert(access_no);
+                    }
+                    continue;
+                }
+            };
+
+            let mut stored = true;
+            let mut staged = false;
+            let mut rows = filings.len();
+            for sink in targets {
+                match sink.write(&access_no, &filings) {
+                    Ok(_) if sink.batches() => staged = true,
+                    Ok(written) => rows = rows.min(written),
+                    Err(err) => {
+                        stored = false;
+                        error!(%err, "Failed to store filing");
+                    }
+                }
+            }
+
+            // already counted by the run that stored it
+            if !persisted {
+                match (stored, staged) {
+                    (true, true) => (),
+                    (true, false) => {
+                        tally.inserted += rows;
+                        metrics::count_filing("stored");
+                        info!(rows, inserted = tally.inserted, "Stored filing");
+                    },
+                    (false, staged) => {
+                        tally.failed += filings.len();
+                        if staged {
+                            tally.failed_staged.insert(access_no.clone());
+                        }
+                        tally.failed_filings.insert(access_no);
+                    }
+                }
+            }
+
+            // a write can complete a batch holding this and earlier filings
+            for sink in sinks.iter_mut() {
+                tally.add_loaded(sink.take_loaded());
+            }
+        }
+
+        (tally, sinks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded(stored: &[(&str, usize)], failed: &[(&str, usize)]) -> Loaded {
+        let own = |filings: &[(&str, usize)]| filings.iter().map(|(access_no, rows)| (access_no.to_string(), *rows)).collect();
+        Loaded { stored: own(stored), failed: own(failed) }
+    }
+
+    #[test]
+    fn loaded_filings_are_counted_once() {
+        let mut tally = Tally::default();
+        tally.add_loaded(loaded(&[("a", 3), ("b", 2)], &[("c", 4)]));
+        tally.add_loaded(Loaded::default());
+
+        assert_eq!((tally.inserted, tally.failed), (5, 4));
+        assert_eq!(tally.failed_filings, HashSet::from(["c".to_string()]));
+    }
+
+    #[test]
+    fn staged_filings_another_sink_failed_stay_failed() {
+        // the writer counted both as failed when the database refused them
+        let mut tally = Tally { failed: 5, ..Tally::default() };
+        for access_no in ["a", "b"] {
+            tally.failed_staged.insert(access_no.to_string());
+            tally.failed_filings.insert(access_no.to_string());
+        }
+
+        tally.add_loaded(loaded(&[("a", 3)], &[("b", 2)]));
+
+        assert_eq!((tally.inserted, tally.failed), (0, 5));
+        assert!(tally.failed_staged.is_empty());
+        assert_eq!(tally.failed_filings.len(), 2);
+    }
+}
@@ -0,0 +1,148 @@
+use std::panic;
+use chrono::NaiveDate;
+use diesel::r2d2::PoolError;
+use tracing::{error, info};
+
+use crate::{
+    archive::{Archive, ArchiveRecord, RawKind},
+    secweb::{models::FilingTransaction, archives_url, FilingDoc, PARSER_VERSION},
+    database::{get_connection_pool, SqlHelper}};
+
+#[derive(Debug, Default, Clone)]
+pub struct ReparseFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub form_types: Vec<String>,
+    pub issuer_cik: Option<String>,
+}
+
+impl ReparseFilter {
+    fn matches_record(&self, record: &ArchiveRecord) -> bool {
+        if record.kind != RawKind::Filing {
+            return false;
+        }
+
+        if self.from.is_some_and(|from| record.date < from)
+            || self.to.is_some_and(|to| record.date > to) {
+            return false;
+        }
+
+        if self.form_types.is_empty() {
+            return true;
+        }
+
+        let form_type = record.form_type.as_deref().unwrap_or_default();
+        self.form_types.iter().any(|t| t.eq_ignore_ascii_case(form_type))
+    }
+
+    // the index lists a filing under both issuer and owner CIK, so the issuer
+    // can only be checked reliably once the document is parsed
+    fn matches_issuer(&self, filings: &[FilingTransaction]) -> bool {
+        match (&self.issuer_cik, filings.first()) {
+            (None, _) => true,
+            (Some(cik), Some(trans)) => Self::trim_cik(cik) == Self::trim_cik(&trans.company_cik),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn trim_cik(cik: &str) -> &str {
+        cik.trim().trim_start_matches('0')
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReparseStats {
+    pub scanned: usize,
+    pub skipped: usize,
+    pub upserted: usize,
+    pub failed: usize,
+}
+
+/// Rebuilds database rows from the raw archive without touching the network.
+pub struct Reparser {
+    archive: Archive,
+    filter: ReparseFilter,
+}
+
+impl Reparser {
+    pub fn new(archive: Archive, filter: ReparseFilter) -> Reparser {
+        Reparser { archive, filter }
+    }
+
+    /// Fails only when the database can't be reached, filings that fail to
+    /// reparse or store are counted in the stats.
+    pub fn run(&self) -> Result<ReparseStats, PoolError> {
+        let pool = get_connection_pool();
+        let mut conn = pool.get()?;
+        let mut helper = SqlHelper::new();
+        let mut stats = ReparseStats::default();
+
+        let mut records: Vec<_> = self.archive
+            .records()
+            .filter(|r| self.filter.matches_record(r))
+            .collect();
+        records.sort_by_key(|r| r.date);
+
+        let total = records.len();
+        info!(total, parser_version = PARSER_VERSION, "Reparsing archived filings");
+
+        for record in records {
+            stats.scanned += 1;
+
+            let filings = match self.parse(record) {
+                Ok(filings) => filings,
+                Err(err) => {
+                    stats.failed += 1;
+                    error!(access_no = %record.key, %err, "Error reparsing filing");
+                    continue;
+                }
+            };
+
+            // rows stored by an older parser must not outlive it
+            if filings.is_empty() {
+                let issuer_cik = self.filter.issuer_cik.as_deref();
+                match SqlHelper::delete_transactions(&mut conn, &record.key, issuer_cik) {
+                    Ok(0) => stats.skipped += 1,
+                    Ok(rows) => {
+                        stats.upserted += 1;
+                        info!(access_no = %record.key, rows, "Deleted rows of filing without transactions");
+                    },
+                    Err(err) => {
+                        stats.failed += 1;
+                        error!(access_no = %record.key, %err, "Failed to delete rows of filing");
+                    }
+                }
+                continue;
+            }
+
+            if !self.filter.matches_issuer(&filings) {
+                stats.skipped += 1;
+                continue;
+            }
+
+            match helper.upsert_filing(&mut conn, &filings, PARSER_VERSION) {
+                Ok(rows) => {
+                    stats.upserted += 1;
+                    info!(access_no = %record.key, rows, scanned = stats.scanned, total, "Upserted filing");
+                },
+                Err(err) => {
+                    stats.failed += 1;
+                    error!(access_no = %record.key, %err, "Failed to upsert filing");
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn parse(&self, record: &ArchiveRecord) -> Result<Vec<FilingTransaction>, String> {
+        let body = self.archive
+            .read_object(&record.sha256)
+            .map_err(|e| e.to_string())?;
+        let url = archives_url(record.filepath.as_deref().unwrap_or_default());
+
+        // the parser still panics on malformed documents; don't let one stop the run
+        panic::catch_unwind(|| FilingDoc::new(&url, &body).map_err(|e| e.to_string()))
+            .map_err(|_| "parser panicked".to_string())?
+    }
+}
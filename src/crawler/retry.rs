@@ -0,0 +1,58 @@
+use std::{collections::HashSet, fs, io, path::Path};
+use chrono::Utc;
+use tracing::info;
+
+use crate::{
+    archive::SharedArchive,
+    secweb::{archives_url, failed_path, parse_index_line, Edgar, IndexEntry}};
+
+use super::pipeline::{Pipeline, PipelineStats};
+
+/// Entries recorded in a failed list. Older lists only hold the filing path,
+/// those get today's date and no form type.
+fn read_failed(path: &Path) -> io::Result<Vec<IndexEntry>> {
+    let mut seen = HashSet::new();
+    let entries = fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| parse_index_line(line).or_else(|| {
+            let filepath = line.trim_start_matches(&archives_url(""));
+            Some(IndexEntry {
+                company_cik: filepath.split('/').nth(2)?.to_string(),
+                company_name: String::new(),
+                form_type: String::new(),
+                file_date: Utc::now().date_naive(),
+                filepath: filepath.to_string(),
+            })
+        }))
+        .filter(|entry| seen.insert(entry.access_no()))
+        .collect();
+
+    Ok(entries)
+}
+
+/// Sends everything in failed.txt through the pipeline again. The list is
+/// set aside first so entries failing again are recorded afresh.
+pub async fn retry_failed(edgar: Edgar, archive: SharedArchive, batch: usize) -> io::Result<PipelineStats> {
+    let failed = failed_path();
+    let retrying = failed.with_extension("txt.retry");
+    if !retrying.exists() {
+        if !failed.exists() {
+            return Ok(PipelineStats::default());
+        }
+
+        fs::rename(&failed, &retrying)?;
+    }
+
+    let entries = read_failed(&retrying)?;
+    info!(filings = entries.len(), "Retrying failed filings");
+
+    let stats = Pipeline::new(edgar, batch)
+        .with_archive(archive)
+        .run(entries)
+        .await;
+
+    fs::remove_file(&retrying)?;
+    Ok(stats)
+}
@@ -0,0 +1,93 @@
+use std::{process, time::Duration};
+use tokio::{signal, sync::{oneshot, watch}, time};
+use tracing::{info, warn};
+
+/// Set once SIGTERM or SIGINT arrives. Crawls check it between units of work
+/// and stop taking new ones, while whatever is already in flight drains.
+#[derive(Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Never triggers, for runs that don't listen to signals.
+    pub fn never() -> Shutdown {
+        let (_, rx) = watch::channel(false);
+        Shutdown { rx }
+    }
+
+    /// Triggers on the first SIGTERM or SIGINT. A second one exits right away
+    /// for when draining takes too long.
+    pub fn listen() -> Shutdown {
+        let (tx, rx) = watch::channel(false);
+
+        tokio::spawn(async move {
+            Self::signal().await;
+            info!("Shutting down, finishing work in flight");
+            let _ = tx.send(true);
+
+            Self::signal().await;
+            warn!("Exiting without draining");
+            process::exit(130);
+        });
+
+        Shutdown { rx }
+    }
+
+    /// A shutdown that triggers with this one or once the returned sender is
+    /// used or dropped, to stop one unit of work without stopping the rest.
+    pub fn child(&self) -> (Shutdown, oneshot::Sender<()>) {
+        let parent = self.clone();
+        let (tx, rx) = watch::channel(self.is_triggered());
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = parent.wait() => (),
+                _ = cancel_rx => (),
+            }
+            let _ = tx.send(true);
+        });
+
+        (Shutdown { rx }, cancel_tx)
+    }
+
+    #[cfg(unix)]
+    async fn signal() {
+        let mut term = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Could not listen for SIGTERM");
+
+        tokio::select! {
+            _ = signal::ctrl_c() => (),
+            _ = term.recv() => (),
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn signal() {
+        let _ = signal::ctrl_c().await;
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Resolves once shutdown is triggered, never for `Shutdown::never`.
+    pub async fn wait(&self) {
+        let mut rx = self.rx.clone();
+        while !*rx.borrow_and_update() {
+            if rx.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Sleeps for `duration` unless shut down first, returning whether the
+    /// whole duration passed.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = time::sleep(duration) => true,
+            _ = self.wait() => false,
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write}};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::{
+    database::{bulk::{BulkLoader, BULK_ROWS}, crawl_state::{self, EntryStatus}, db, DbConnection, SqlHelper},
+    metrics,
+    secweb::{archives_url, models::FilingTransaction, save_failed, IndexEntry, PARSER_VERSION}};
+
+use super::Sink;
+
+/// Filings a batching sink is done with, by accession number with their
+/// transaction counts.
+#[derive(Debug, Default)]
+pub struct Loaded {
+    pub stored: Vec<(String, usize)>,
+    pub failed: Vec<(String, usize)>,
+}
+
+/// A destination for parsed filings. The pipeline's writer stage hands every
+/// filing to each sink in turn, off the async runtime.
+pub trait FilingSink: Send {
+    /// Stores the transactions of one filing, returning how many were written.
+    /// Sinks that `batches` only stage them here.
+    fn write(&mut self, access_no: &str, filings: &[FilingTransaction]) -> Result<usize, Box<dyn Error>>;
+
+    /// A filing that could not be fetched or parsed.
+    fn write_failed(&mut self, _access_no: &str, _error: &str) {}
+
+    /// Whether written filings are only staged, they count as stored once
+    /// `take_loaded` or `finish` reports them.
+    fn batches(&self) -> bool {
+        false
+    }
+
+    /// Filings of the batches loaded since the last call.
+    fn take_loaded(&mut self) -> Loaded {
+        Loaded::default()
+    }
+
+    /// Whether filings an earlier run already stored have to be written again,
+    /// true for files that are rebuilt on every run.
+    fn replays_stored(&self) -> bool {
+        false
+    }
+
+    /// Called once the pipeline has drained, `complete` is false when it was
+    /// interrupted before running out of filings. Batching sinks load what is
+    /// still staged and report it.
+    fn finish(self: Box<Self>, _complete: bool) -> Loaded {
+        Loaded::default()
+    }
+}
+
+/// The sink writing `kind` to the day's file at `path` without extension,
+/// `None` for the database, see `DatabaseSink` and `BulkSink`.
+pub fn day_file_sink(kind: Sink, path: &str) -> Option<Box<dyn FilingSink>> {
+    let path = format!("{path}.{}", kind.extension()?);
+
+    let sink: Box<dyn FilingSink> = match kind {
+        Sink::Json => Box::new(JsonSink::create(&path)),
+        Sink::Ndjson => Box::new(NdjsonSink::create(&path)),
+        Sink::Csv => Box::new(CsvSink::create(&path)),
+        Sink::Database | Sink::Stdout => unreachable!("{kind:?} has no day file"),
+    };
+
+    Some(sink)
+}
+
+/// Inserts into the database, one transaction per filing, replacing whatever
+/// an earlier run stored under the accession number. With entry tracking every
+/// filing's outcome is also recorded in crawl_entry. Runs on the database
+/// threads, connecting on the first filing.
+#[derive(Default)]
+pub struct DatabaseSink {
+    helper: SqlHelper,
+    tracked: bool,
+}
+
+impl DatabaseSink {
+    pub fn new() -> DatabaseSink {
+        DatabaseSink::default()
+    }
+
+    pub fn with_entry_tracking(mut self) -> DatabaseSink {
+        self.tracked = true;
+        self
+    }
+}
+
+/// Records an entry's outcome on the connection the job already holds, a
+/// second checkout could wait forever on a pool of one.
+fn mark_entry(conn: &mut DbConnection, access_no: &str, status: EntryStatus, error: Option<&str>) {
+    if let Err(err) = crawl_state::mark_entry(conn, access_no, status, error) {
+        warn!(%access_no, status = status.as_str(), %err, "Could not record entry");
+    }
+}
+
+/// `mark_entry` for an entry that failed before reaching the sink.
+fn mark_failed(access_no: &str, error: &str) {
+    let (entry, error) = (access_no.to_string(), error.to_string());
+    let marked = db().run_blocking(move |conn| {
+        mark_entry(conn, &entry, EntryStatus::Failed, Some(&error));
+        Ok::<_, Infallible>(())
+    });
+
+    if let Err(err) = marked {
+        warn!(%access_no, %err, "Could not record entry");
+    }
+}
+
+impl FilingSink for DatabaseSink {
+    fn write(&mut self, access_no: &str, filings: &[FilingTransaction]) -> Result<usize, Box<dyn Error>> {
+        let mut helper = self.helper.clone();
+        let tracked = self.tracked;
+        let access_no = access_no.to_string();
+        let filings = filings.to_vec();
+
+        // the outer error is the connection's, the inner one the filing's
+        let stored = db()
+            .run_blocking(move |conn| {
+                let stored = helper.upsert_filing(conn, &filings, PARSER_VERSION);
+                if tracked {
+                    match &stored {
+                        Ok(_) => mark_entry(conn, &access_no, EntryStatus::Persisted, None),
+                        Err(err) => mark_entry(conn, &access_no, EntryStatus::Failed, Some(&err.to_string())),
+                    }
+                }
+                Ok::<_, Infallible>(stored)
+            })
+            .inspect_err(|_| metrics::count_failure("database"))?;
+
+        stored.map_err(|err| {
+            metrics::count_failure("insert");
+            err.into()
+        })
+    }
+
+    fn write_failed(&mut self, access_no: &str, error: &str) {
+        if self.tracked {
+            mark_failed(access_no, error);
+        }
+    }
+}
+
+/// A filing waiting in `BulkSink`. The index entry is rebuilt from its rows
+/// for failed.txt, filings without rows have none.
+struct StagedFiling {
+    access_no: String,
+    rows: usize,
+    entry: Option<IndexEntry>,
+}
+
+impl StagedFiling {
+    fn new(access_no: &str, filings: &[FilingTransaction]) -> StagedFiling {
+        let entry = filings.first().map(|trans| IndexEntry {
+            company_cik: trans.company_cik.clone(),
+            company_name: trans.company.clone(),
+            form_type: trans.form_type.clone(),
+            file_date: trans.form_date,
+            filepath: trans.form_url.trim_start_matches(&archives_url("")).to_string(),
+        });
+
+        StagedFiling { access_no: access_no.to_string(), rows: filings.len(), entry }
+    }
+}
+
+/// Stages filings and stores them with `BulkLoader` once `BULK_ROWS`
+/// transactions are waiting and when the pipeline drains. Filings count as
+/// stored and their entries are marked persisted only after the load holding
+/// them committed. When the merge fails the filings are stored one by one,
+/// and those that still fail are listed in failed.txt for `retry-failed`.
+#[derive(Default)]
+pub struct BulkSink {
+    loader: BulkLoader,
+    staged: Vec<StagedFiling>,
+    loaded: Loaded,
+    tracked: bool,
+}
+
+impl BulkSink {
+    pub fn new() -> BulkSink {
+        BulkSink::default()
+    }
+
+    pub fn with_entry_tracking(mut self) -> BulkSink {
+        self.tracked = true;
+        self
+    }
+
+    fn load(&mut self) {
+        let staged = std::mem::take(&mut self.staged);
+        let mut loader = std::mem::take(&mut self.loader);
+        let access_nos: Vec<_> = staged.iter().map(|filing| filing.access_no.clone()).collect();
+        let tracked = self.tracked;
+
+        // marked in the same job, so a pool of one can't stall on a second checkout
+        let result = db().run_blocking(move |conn| {
+            let loaded = match loader.is_empty() {
+                // only filings without rows
+                true => Ok(Vec::new()),
+                false => loader.load(conn).map(|stats| stats.failed).map_err(|e| e.to_string()),
+            };
+
+            let marked = match (tracked, &loaded) {
+                (false, _) => Ok(()),
+                (true, Ok(failed)) => {
+                    let stored: Vec<_> = access_nos.into_iter()
+                        .filter(|access_no| !failed.iter().any(|(failed, _)| failed == access_no))
+                        .collect();
+                    for (access_no, err) in failed {
+                        mark_entry(conn, access_no, EntryStatus::Failed, Some(err));
+                    }
+                    crawl_state::mark_entries(conn, &stored, EntryStatus::Persisted, None).map(|_| ())
+                },
+                (true, Err(err)) => crawl_state::mark_entries(conn, &access_nos, EntryStatus::Failed, Some(err)).map(|_| ()),
+            };
+
+            Ok::<_, Infallible>((loaded, marked))
+        });
+
+        let loaded = result.and_then(|(loaded, marked)| {
+            if let Err(err) = marked {
+                warn!(entries = staged.len(), %err, "Could not record entries");
+            }
+            loaded
+        });
+
+        let failed: HashSet<String> = match loaded {
+            Ok(failed) => {
+                for (access_no, err) in &failed {
+                    metrics::count_failure("insert");
+                    error!(%access_no, %err, "Failed to store filing");
+                }
+                failed.into_iter().map(|(access_no, _)| access_no).collect()
+            },
+            Err(err) => {
+                metrics::count_failure("insert");
+                error!(filings = staged.len(), %err, "Bulk load failed");
+                staged.iter().map(|filing| filing.access_no.clone()).collect()
+            }
+        };
+
+        for filing in staged {
+            match failed.contains(&filing.access_no) {
+                false => self.loaded.stored.push((filing.access_no, filing.rows)),
+                true => {
+                    if let Some(entry) = &filing.entry {
+                        save_failed(entry);
+                    }
+                    self.loaded.failed.push((filing.access_no, filing.rows));
+                }
+            }
+        }
+    }
+}
+
+impl FilingSink for BulkSink {
+    // a failed load is reported through `take_loaded`, not as this filing's error
+    fn write(&mut self, access_no: &str, filings: &[FilingTransaction]) -> Result<usize, Box<dyn Error>> {
+        self.loader.stage(filings, PARSER_VERSION);
+        self.staged.push(StagedFiling::new(access_no, filings));
+
+        if self.loader.rows() >= BULK_ROWS {
+            self.load();
+        }
+
+        Ok(0)
+    }
+
+    fn write_failed(&mut self, access_no: &str, error: &str) {
+        if self.tracked {
+            mark_failed(access_no, error);
+        }
+    }
+
+    fn batches(&self) -> bool {
+        true
+    }
+
+    fn take_loaded(&mut self) -> Loaded {
+        std::mem::take(&mut self.loaded)
+    }
+
+    // staged filings are whole, so they're stored even after an interruption
+    fn finish(mut self: Box<Self>, _complete: bool) -> Loaded {
+        if !self.staged.is_empty() {
+            self.load();
+        }
+
+        self.loaded
+    }
+}
+
+/// A file written to `<path>.part` and renamed on completion, so an
+/// interrupted day never leaves a valid but partial file.
+struct PartFile {
+    path: String,
+    writer: BufWriter<File>,
+}
+
+impl PartFile {
+    fn create(path: &str) -> PartFile {
+        let writer = BufWriter::new(
+            File::create(Self::part_path(path)).expect("Unable to create file")
+        );
+
+        PartFile { path: path.to_string(), writer }
+    }
+
+    fn part_path(path: &str) -> String {
+        format!("{path}.part")
+    }
+
+    fn finish(mut self, complete: bool) {
+        self.writer.flush().expect("Unable to write file");
+        Self::commit(&self.path, complete);
+    }
+
+    /// Moves a finished file into place, an unfinished one is left as `.part`.
+    fn commit(path: &str, complete: bool) {
+        let part = Self::part_path(path);
+        match complete {
+            true => fs::rename(&part, path).expect("Unable to move file"),
+            false => warn!(path = %part, "Leaving incomplete file"),
+        }
+    }
+}
+
+/// One JSON array per day.
+pub struct JsonSink {
+    file: PartFile,
+    empty: bool,
+}
+
+impl JsonSink {
+    pub fn create(path: &str) -> JsonSink {
+        let mut file = PartFile::create(path);
+        file.writer.write_all(b"[").expect("Unable to write file");
+
+        JsonSink { file, empty: true }
+    }
+}
+
+impl FilingSink for JsonSink {
+    fn write(&mut self, _access_no: &str, filings: &[FilingTransaction]) -> Result<usize, Box<dyn Error>> {
+        for trans in filings {
+            if !self.empty {
+                self.file.writer.write_all(b",")?;
+            }
+
+            serde_json::to_writer(&mut self.file.writer, trans)?;
+            self.empty = false;
+        }
+
+        Ok(filings.len())
+    }
+
+    fn replays_stored(&self) -> bool {
+        true
+    }
+
+    fn finish(mut self: Box<Self>, complete: bool) -> Loaded {
+        if complete {
+            self.file.writer.write_all(b"]").expect("Unable to write file");
+        }
+        self.file.finish(complete);
+        Loaded::default()
+    }
+}
+
+/// One transaction per line, easy to append to and stream.
+pub struct NdjsonSink {
+    file: PartFile,
+}
+
+impl NdjsonSink {
+    pub fn create(path: &str) -> NdjsonSink {
+        NdjsonSink { file: PartFile::create(path) }
+    }
+}
+
+impl FilingSink for NdjsonSink {
+    fn write(&mut self, _access_no: &str, filings: &[FilingTransaction]) -> Result<usize, Box<dyn Error>> {
+        write_lines(&mut self.file.writer, filings)
+    }
+
+    fn replays_stored(&self) -> bool {
+        true
+    }
+
+    fn finish(self: Box<Self>, complete: bool) -> Loaded {
+        self.file.finish(complete);
+        Loaded::default()
+    }
+}
+
+/// Transactions as CSV rows with a header, relationships joined by `|`.
+pub struct CsvSink {
+    path: String,
+    writer: csv::Writer<File>,
+}
+
+impl CsvSink {
+    pub fn create(path: &str) -> CsvSink {
+        let writer = csv::Writer::from_path(PartFile::part_path(path)).expect("Unable to create file");
+        CsvSink { path: path.to_string(), writer }
+    }
+}
+
+/// `FilingTransaction` with the relationship list flattened, CSV has no
+/// room for nested values.
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    access_no: &'a str,
+    form_type: &'a str,
+    form_date: chrono::NaiveDate,
+    trans_date: chrono::NaiveDate,
+    company_cik: &'a str,
+    company: &'a str,
+    symbol: &'a str,
+    owner_cik: &'a str,
+    owner: &'a str,
+    relationship: String,
+    trans_code: &'a str,
+    action_code: &'a str,
+    ownership_code: &'a str,
+    shares_traded: f32,
+    avg_price: f32,
+    amount: f32,
+    shares_owned: f32,
+    form_url: &'a str,
+    web_url: &'a str,
+}
+
+impl<'a> From<&'a FilingTransaction> for CsvRow<'a> {
+    fn from(trans: &'a FilingTransaction) -> Self {
+        let relationship = trans.relationship.iter()
+            .map(|r| format!("{r:?}"))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        CsvRow {
+            access_no: &trans.access_no,
+            form_type: &trans.form_type,
+            form_date: trans.form_date,
+            trans_date: trans.trans_date,
+            company_cik: &trans.company_cik,
+            company: &trans.company,
+            symbol: &trans.symbol,
+            owner_cik: &trans.owner_cik,
+            owner: &trans.owner,
+            relationship,
+            trans_code: &trans.trans_code,
+            action_code: &trans.action_code,
+            ownership_code: &trans.ownership_code,
+            shares_traded: trans.shares_traded,
+            avg_price: trans.avg_price,
+            amount: trans.amount,
+            shares_owned: trans.shares_owned,
+            form_url: &trans.form_url,
+            web_url: &trans.web_url,
+        }
+    }
+}
+
+impl FilingSink for CsvSink {
+    fn write(&mut self, _access_no: &str, filings: &[FilingTransaction]) -> Result<usize, Box<dyn Error>> {
+        for trans in filings {
+            self.writer.serialize(CsvRow::from(trans))?;
+        }
+
+        Ok(filings.len())
+    }
+
+    fn replays_stored(&self) -> bool {
+        true
+    }
+
+    fn finish(mut self: Box<Self>, complete: bool) -> Loaded {
+        self.writer.flush().expect("Unable to write file");
+        PartFile::commit(&self.path, complete);
+        Loaded::default()
+    }
+}
+
+/// NDJSON on standard output, for piping into other tools. Logs go to
+/// standard error so they never mix with it.
+pub struct StdoutSink {
+    stdout: io::Stdout,
+    written: usize,
+}
+
+impl StdoutSink {
+    pub fn new() -> StdoutSink {
+        StdoutSink { stdout: io::stdout(), written: 0 }
+    }
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FilingSink for StdoutSink {
+    fn write(&mut self, _access_no: &str, filings: &[FilingTransaction]) -> Result<usize, Box<dyn Error>> {
+        let written = write_lines(&mut self.stdout.lock(), filings)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn finish(self: Box<Self>, _complete: bool) -> Loaded {
+        let _ = self.stdout.lock().flush();
+        info!(written = self.written, "Wrote transactions to stdout");
+        Loaded::default()
+    }
+}
+
+fn write_lines<W: Write>(writer: &mut W, filings: &[FilingTransaction]) -> Result<usize, Box<dyn Error>> {
+    for trans in filings {
+        serde_json::to_writer(&mut *writer, trans)?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(filings.len())
+}
@@ -0,0 +1,81 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf}};
+use chrono::NaiveDate;
+use tokio::{sync::mpsc, task};
+use tracing::{info, warn};
+
+use crate::{
+    archive::SharedArchive,
+    config::config,
+    secweb::{tarball::{feed_tarball_name, read_feed_tarball}, Edgar}};
+
+use super::pipeline::{Pipeline, PipelineStats};
+
+/// Processes whole days from EDGAR's daily feed tarballs, one download per day
+/// instead of a request per filing.
+pub struct FeedIngest {
+    edgar: Edgar,
+    archive: SharedArchive,
+    form_types: Vec<String>,
+    batch: usize,
+}
+
+impl FeedIngest {
+    pub fn new(edgar: Edgar, archive: SharedArchive, form_types: &[String], batch: usize) -> FeedIngest {
+        FeedIngest { edgar, archive, form_types: form_types.to_vec(), batch }
+    }
+
+    /// Downloaded tarballs are kept next to the raw archive, laid out like EDGAR.
+    fn local_path(date: NaiveDate) -> PathBuf {
+        config().storage.archive_dir.join(feed_tarball_name(date))
+    }
+
+    /// A day without tarball, like a holiday, has nothing to ingest.
+    pub async fn ingest_date(&self, date: NaiveDate) -> Result<PipelineStats, String> {
+        let path = Self::local_path(date);
+
+        if path.exists() {
+            info!(path = %path.display(), "Using downloaded tarball");
+        } else {
+            match self.edgar.download_feed_tarball(date, &path).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    warn!(%date, "No feed tarball");
+                    return Ok(PipelineStats::default());
+                },
+                Err(err) => return Err(format!("Error downloading feed tarball: {err}")),
+            }
+        }
+
+        self.ingest_file(&path).await
+    }
+
+    pub async fn ingest_file(&self, path: &Path) -> Result<PipelineStats, String> {
+        let (tx, rx) = mpsc::channel(self.batch * 2);
+        let form_types = self.form_types.clone();
+        let tar_path = path.to_path_buf();
+
+        // decompressing is blocking work, submissions are handed over as they come
+        let reader = task::spawn_blocking(move || {
+            let file = File::open(&tar_path)?;
+            read_feed_tarball(BufReader::new(file), &form_types, |submission| {
+                // only fails once the pipeline has stopped, the rest is skipped
+                let _ = tx.blocking_send((submission.index_entry(), submission.body));
+            })
+        });
+
+        let stats = Pipeline::new(self.edgar.clone(), self.batch)
+            .with_archive(self.archive.clone())
+            .run_documents(rx)
+            .await;
+
+        match reader.await.unwrap() {
+            Ok(total) => info!(total, path = %path.display(), "Read submissions"),
+            Err(err) => return Err(format!("Error reading tarball: {err}")),
+        }
+
+        Ok(stats)
+    }
+}
@@ -0,0 +1,162 @@
+use std::{env, process, time::Duration};
+use tokio::{sync::oneshot, task::JoinHandle, time};
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::{
+    database::{crawl_state::{self, CrawlStatus}, db},
+    metrics,
+    secweb::calendar::is_business_day};
+
+use super::{Crawler, IndexOutcome};
+
+/// How long a leased day stays with a worker that stopped renewing it.
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(600);
+const IDLE_DELAY: Duration = Duration::from_secs(30);
+
+/// Crawls days leased from crawl_day, so any number of workers on any number
+/// of machines can share the days planned with `Crawler::plan_range`.
+pub struct Worker {
+    id: String,
+    crawler: Crawler,
+    lease: Duration,
+}
+
+impl Worker {
+    /// The crawler must write to the database, that's where the leases live.
+    pub fn new(mut crawler: Crawler) -> Worker {
+        let id = Self::default_id();
+        crawler.leased_by = Some(id.clone());
+        Worker { id, crawler, lease: DEFAULT_LEASE }
+    }
+
+    pub fn with_id(mut self, id: &str) -> Worker {
+        self.id = id.to_string();
+        self.crawler.leased_by = Some(self.id.clone());
+        self
+    }
+
+    pub fn with_lease(mut self, lease: Duration) -> Worker {
+        self.lease = lease;
+        self
+    }
+
+    /// Host name and process id, enough to tell workers apart in crawl_day.
+    fn default_id() -> String {
+        let host = env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string());
+        format!("{host}-{}", process::id())
+    }
+
+    /// Leases and crawls days until none are left, returning how many were
+    /// crawled. Days given back for later are deferred while the worker goes
+    /// on with others. With `follow` it never stops and plans each new
+    /// business day itself once it runs out of work.
+    pub async fn run(&mut self, batch: usize, follow: bool) -> usize {
+        let mut crawled = 0;
+
+        while !self.crawler.shutdown.is_triggered() {
+            let id = self.id.clone();
+            let lease_secs = self.lease.as_secs() as i32;
+            let leased = self.crawler
+                .with_state(move |conn| crawl_state::lease_day(conn, &id, lease_secs))
+                .await;
+
+            let date = match leased {
+                Some(Some(date)) => date,
+                Some(None) if !follow => {
+                    let deferred = self.crawler.with_state(crawl_state::has_deferred_days).await;
+                    if deferred != Some(true) {
+                        break;
+                    }
+
+                    self.crawler.shutdown.sleep(IDLE_DELAY).await;
+                    continue;
+                },
+                Some(None) => {
+                    let yesterday = Crawler::yesterday();
+                    if is_business_day(yesterday) {
+                        self.crawler.with_state(move |conn| crawl_state::plan_days(conn, &[yesterday])).await;
+                    }
+
+                    self.crawler.shutdown.sleep(IDLE_DELAY).await;
+                    continue;
+                },
+                // already logged, the database may come back
+                None => {
+                    self.crawler.shutdown.sleep(IDLE_DELAY).await;
+                    continue;
+                }
+            };
+
+            let span = info_span!("day", crawl_date = %date, worker = %self.id);
+            span.in_scope(|| info!("Leased day"));
+            self.crawler.crawl_date = date;
+
+            let lag = (Crawler::yesterday() - date).num_days().max(0);
+            metrics::metrics().crawl_lag_days.set(lag);
+
+            // losing the lease stops the day like a shutdown, without stopping the worker
+            let shutdown = self.crawler.shutdown.clone();
+            let (day_shutdown, cancel) = shutdown.child();
+            self.crawler.shutdown = day_shutdown;
+
+            let heartbeat = span.in_scope(|| self.heartbeat(cancel));
+            let result = self.crawler
+                .crawl_from_index(batch, Some(CrawlStatus::InProgress))
+                .instrument(span)
+                .await;
+            heartbeat.abort();
+            self.crawler.shutdown = shutdown;
+            crawled += 1;
+
+            if let Some(budget) = &self.crawler.budget {
+                budget.prune().await;
+            }
+
+            // the day went back to the pool, other days can be crawled while
+            // EDGAR gets a moment
+            if let IndexOutcome::RetryAfter(delay) = result {
+                let (id, delay) = (self.id.clone(), delay.as_secs() as i32);
+                self.crawler.with_state(move |conn| crawl_state::defer_day(conn, date, &id, delay)).await;
+            }
+        }
+
+        match self.crawler.shutdown.is_triggered() {
+            true => info!(worker = %self.id, "Worker stopped"),
+            false => info!(worker = %self.id, "Worker found no more days to crawl"),
+        }
+        crawled
+    }
+
+    /// Keeps renewing the lease on the crawl date until aborted, and uses
+    /// `cancel` once the lease is lost.
+    fn heartbeat(&self, cancel: oneshot::Sender<()>) -> JoinHandle<()> {
+        let date = self.crawler.crawl_date;
+        let id = self.id.clone();
+        let lease = self.lease;
+
+        tokio::spawn(async move {
+            let mut ticker = time::interval(lease / 3);
+            // the first tick completes right away, the lease is fresh then
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let id = id.clone();
+                let renewed = db()
+                    .run(move |conn| crawl_state::renew_lease(conn, date, &id, lease.as_secs() as i32))
+                    .await;
+
+                match renewed {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        warn!("Lost lease, stopping the day another worker may be crawling");
+                        let _ = cancel.send(());
+                        return;
+                    },
+                    Err(err) => warn!(%err, "Could not renew lease"),
+                }
+            }
+        }.in_current_span())
+    }
+}
@@ -0,0 +1,352 @@
+//! Bulk loading for backfills and data set imports. Parsed filings are staged
+//! in memory, copied into a temporary table with `COPY ... FROM STDIN` and
+//! merged into issuer, individual, form and non_deriv_transaction with one
+//! statement each, all in one transaction, instead of a few round trips per
+//! filing. Filings replace what was stored under their accession number, like
+//! `SqlHelper::upsert_filing`. When the merge fails the batch is stored filing
+//! by filing instead, so one bad filing doesn't take the others with it.
+//! SQLite has no `COPY`, there every staged filing is upserted inside a single
+//! transaction instead.
+
+use std::time::{Duration, Instant};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel::ExecuteCopyFromDsl;
+use tracing::{info, warn};
+
+use super::{
+    insert_models::{NewForm, NewIndividual, NewIssuer, NewNonDerivTransaction},
+    sql_types::IntVec,
+    DbConnection, SqlHelper};
+use crate::{metrics::count_rows, secweb::models::FilingTransaction};
+
+/// Transactions to stage before loading them, a few tens of MB in memory.
+pub const BULK_ROWS: usize = 50_000;
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::schema::sql_types::IntList;
+
+    staged_transaction (row_no) {
+        row_no -> Int4,
+        access_no -> Varchar,
+        form_type -> Varchar,
+        form_date -> Date,
+        txt_url -> Varchar,
+        web_url -> Varchar,
+        issuer_cik -> Varchar,
+        issuer_name -> Varchar,
+        symbol -> Varchar,
+        owner_cik -> Varchar,
+        full_name -> Varchar,
+        first_name -> Nullable<Varchar>,
+        last_name -> Nullable<Varchar>,
+        trans_date -> Date,
+        action_code -> Nullable<Varchar>,
+        ownership_code -> Nullable<Varchar>,
+        transaction_code -> Nullable<Varchar>,
+        shares_balance -> Numeric,
+        shares_traded -> Numeric,
+        avg_price -> Numeric,
+        amount -> Numeric,
+        relationships -> IntList,
+        parser_version -> Int4,
+    }
+}
+
+const CREATE_STAGING: &str = r#"
+create temp table staged_transaction
+(
+    row_no           integer        not null,
+    access_no        varchar        not null,
+    form_type        varchar        not null,
+    form_date        date           not null,
+    txt_url          varchar        not null,
+    web_url          varchar        not null,
+    issuer_cik       varchar        not null,
+    issuer_name      varchar        not null,
+    symbol           varchar        not null,
+    owner_cik        varchar        not null,
+    full_name        varchar        not null,
+    first_name       varchar,
+    last_name        varchar,
+    trans_date       date           not null,
+    action_code      varchar,
+    ownership_code   varchar,
+    transaction_code varchar,
+    shares_balance   numeric(20, 3) not null,
+    shares_traded    numeric(20, 3) not null,
+    avg_price        numeric(20, 3) not null,
+    amount           numeric(20, 3) not null,
+    relationships    integer[]      not null,
+    parser_version   integer        not null
+) on commit drop;
+"#;
+
+// a filing's issuer and owner are those of its first row, as in upsert_filing
+const MERGE_INDIVIDUALS: &str = r#"
+insert into individual (cik, "FullName", "FirstName", "LastName")
+select distinct on (owner_cik) owner_cik, full_name, first_name, last_name
+from staged_transaction
+order by owner_cik, row_no
+on conflict (cik) do nothing
+"#;
+
+// issuers are looked up by CIK alone, so only unknown CIKs get a row
+const MERGE_ISSUERS: &str = r#"
+insert into issuer ("Name", "Symbol", cik)
+select distinct on (s.issuer_cik) s.issuer_name, s.symbol, s.issuer_cik
+from staged_transaction s
+where not exists (select 1 from issuer i where i.cik = s.issuer_cik)
+order by s.issuer_cik, s.row_no
+on conflict ("Symbol", cik) do nothing
+"#;
+
+const MERGE_FORMS: &str = r#"
+insert into form ("IssuerId", "DateReported", "FormType", "TxtURL", "WebURL", "AccessNo", "ParserVersion")
+select distinct on (s.access_no) i."IssuerId", s.form_date, s.form_type, s.txt_url, s.web_url, s.access_no, s.parser_version
+from staged_transaction s
+join (
+    select cik, min("IssuerId") as "IssuerId"
+    from issuer
+    where cik in (select issuer_cik from staged_transaction)
+    group by cik
+) i on i.cik = s.issuer_cik
+order by s.access_no, s.row_no
+on conflict ("AccessNo") do update set
+    "IssuerId" = excluded."IssuerId",
+    "DateReported" = excluded."DateReported",
+    "FormType" = excluded."FormType",
+    "TxtURL" = excluded."TxtURL",
+    "WebURL" = excluded."WebURL",
+    "ParserVersion" = excluded."ParserVersion"
+"#;
+
+const DELETE_REPLACED: &str = r#"
+delete from non_deriv_transaction t
+using form f
+where t."FormId" = f."FormId"
+  and f."AccessNo" in (select access_no from staged_transaction)
+"#;
+
+const MERGE_TRANSACTIONS: &str = r#"
+insert into non_deriv_transaction (
+    "DateReported", "FormId", "IssuerId", "IndividualId", "ActionCode", "OwnershipCode", "TransactionCode",
+    "SharesBalance", "SharesTraded", "AvgPrice", "Amount", "Relationships", "ParserVersion")
+select s.trans_date, f."FormId", f."IssuerId", o."IndividualId", s.action_code, s.ownership_code, s.transaction_code,
+    s.shares_balance, s.shares_traded, s.avg_price, s.amount, s.relationships, s.parser_version
+from staged_transaction s
+join form f on f."AccessNo" = s.access_no
+join (
+    select distinct on (access_no) access_no, owner_cik
+    from staged_transaction
+    order by access_no, row_no
+) first on first.access_no = s.access_no
+join individual o on o.cik = first.owner_cik
+order by s.row_no
+"#;
+
+#[derive(Insertable)]
+#[diesel(table_name = staged_transaction, treat_none_as_default_value = false)]
+struct StagedRow {
+    row_no: i32,
+    access_no: String,
+    form_type: String,
+    form_date: chrono::NaiveDate,
+    txt_url: String,
+    web_url: String,
+    issuer_cik: String,
+    issuer_name: String,
+    symbol: String,
+    owner_cik: String,
+    full_name: String,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    trans_date: chrono::NaiveDate,
+    action_code: Option<String>,
+    ownership_code: Option<String>,
+    transaction_code: Option<String>,
+    shares_balance: bigdecimal::BigDecimal,
+    shares_traded: bigdecimal::BigDecimal,
+    avg_price: bigdecimal::BigDecimal,
+    amount: bigdecimal::BigDecimal,
+    relationships: IntVec,
+    parser_version: i32,
+}
+
+impl StagedRow {
+    /// Built from the same insert models as `upsert_filing`, so both paths
+    /// store identical values.
+    fn map(row_no: i32, filing: &FilingTransaction, parser_version: i32) -> StagedRow {
+        let issuer = NewIssuer::map(filing);
+        let owner = NewIndividual::map(filing);
+        let form = NewForm::map(filing, 0);
+        let trans = NewNonDerivTransaction::map(filing, 0, 0, 0);
+
+        StagedRow {
+            row_no,
+            access_no: form.access_no,
+            form_type: form.form_type,
+            form_date: form.date_reported,
+            txt_url: form.txt_url,
+            web_url: form.web_url,
+            issuer_cik: issuer.cik.to_string(),
+            issuer_name: issuer.issuer_name.to_string(),
+            symbol: issuer.issuer_symbol.to_string(),
+            owner_cik: owner.cik.to_string(),
+            full_name: owner.full_name,
+            first_name: owner.first_name,
+            last_name: owner.last_name,
+            trans_date: trans.date_reported,
+            action_code: trans.action_code,
+            ownership_code: trans.ownership_code,
+            transaction_code: trans.transaction_code,
+            shares_balance: trans.shares_balance,
+            shares_traded: trans.shares_traded,
+            avg_price: trans.avg_price,
+            amount: trans.amount,
+            relationships: trans.relationships,
+            parser_version,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BulkStats {
+    pub filings: usize,
+    /// Transactions written
+    pub rows: usize,
+    pub elapsed: Duration,
+    /// Accession numbers and errors of the filings that failed to store
+    /// one by one after the merge failed, the rest of the batch is stored
+    pub failed: Vec<(String, String)>,
+}
+
+impl BulkStats {
+    pub fn rows_per_sec(&self) -> f64 {
+        self.rows as f64 / self.elapsed.as_secs_f64().max(0.001)
+    }
+}
+
+/// Filings waiting to be loaded together.
+#[derive(Default)]
+pub struct BulkLoader {
+    filings: Vec<(Vec<FilingTransaction>, i32)>,
+    rows: usize,
+}
+
+impl BulkLoader {
+    pub fn new() -> BulkLoader {
+        BulkLoader::default()
+    }
+
+    /// Adds one filing's transactions, tagged with `parser_version`.
+    pub fn stage(&mut self, filings: &[FilingTransaction], parser_version: i32) {
+        if filings.is_empty() {
+            return;
+        }
+
+        self.rows += filings.len();
+        self.filings.push((filings.to_vec(), parser_version));
+    }
+
+    /// Transactions staged so far.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn filings(&self) -> usize {
+        self.filings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filings.is_empty()
+    }
+
+    /// Stores everything staged in one transaction and empties the loader,
+    /// whether or not that worked.
+    pub fn load(&mut self, conn: &mut DbConnection) -> Result<BulkStats, Error> {
+        let filings = std::mem::take(&mut self.filings);
+        let staged = std::mem::take(&mut self.rows);
+        let started = Instant::now();
+
+        let mut failed = Vec::new();
+        let rows = match conn {
+            DbConnection::Postgres(pg) => match Self::copy_merge(pg, &filings) {
+                Ok(rows) => rows,
+                Err(err) => {
+                    warn!(filings = filings.len(), %err, "Bulk merge failed, storing filings one by one");
+                    Self::upsert_one_by_one(conn, &filings, &mut failed)
+                }
+            },
+            DbConnection::Sqlite(_) => Self::upsert_each(conn, &filings)?,
+        };
+
+        let stats = BulkStats { filings: filings.len(), rows, elapsed: started.elapsed(), failed };
+        info!(
+            filings = stats.filings, staged, rows = stats.rows, failed = stats.failed.len(),
+            elapsed_ms = stats.elapsed.as_millis() as u64, rows_per_sec = stats.rows_per_sec().round() as u64,
+            "Bulk loaded");
+
+        Ok(stats)
+    }
+
+    fn copy_merge(conn: &mut PgConnection, filings: &[(Vec<FilingTransaction>, i32)]) -> Result<usize, Error> {
+        let rows: Vec<StagedRow> = filings.iter()
+            .flat_map(|(filing, version)| filing.iter().map(move |trans| (trans, *version)))
+            .enumerate()
+            .map(|(i, (trans, version))| StagedRow::map(i as i32, trans, version))
+            .collect();
+
+        conn.transaction(|conn| {
+            conn.batch_execute(CREATE_STAGING)?;
+            diesel::copy_from(staged_transaction::table)
+                .from_insertable(&rows)
+                .execute(conn)?;
+            // temp tables have no statistics until analyzed, which makes the
+            // planner pick nested loops over the whole batch
+            conn.batch_execute("analyze staged_transaction")?;
+
+            count_rows("individual", diesel::sql_query(MERGE_INDIVIDUALS).execute(conn)?);
+            count_rows("issuer", diesel::sql_query(MERGE_ISSUERS).execute(conn)?);
+            count_rows("form", diesel::sql_query(MERGE_FORMS).execute(conn)?);
+
+            diesel::sql_query(DELETE_REPLACED).execute(conn)?;
+            let inserted = diesel::sql_query(MERGE_TRANSACTIONS).execute(conn)?;
+            count_rows("non_deriv_transaction", inserted);
+
+            Ok(inserted)
+        })
+    }
+
+    /// Each filing in its own transaction, collecting those that failed.
+    fn upsert_one_by_one(
+        conn: &mut DbConnection,
+        filings: &[(Vec<FilingTransaction>, i32)],
+        failed: &mut Vec<(String, String)>) -> usize
+    {
+        let mut helper = SqlHelper::new();
+        let mut inserted = 0;
+
+        for (filing, version) in filings {
+            match helper.upsert_filing(conn, filing, *version) {
+                Ok(rows) => inserted += rows,
+                // staged filings always have rows
+                Err(err) => failed.push((filing[0].access_no.clone(), err.to_string())),
+            }
+        }
+
+        inserted
+    }
+
+    fn upsert_each(conn: &mut DbConnection, filings: &[(Vec<FilingTransaction>, i32)]) -> Result<usize, Error> {
+        let mut helper = SqlHelper::new();
+
+        conn.transaction(|conn| {
+            filings.iter().try_fold(0, |inserted, (filing, version)| {
+                Ok(inserted + helper.upsert_filing(conn, filing, *version)?)
+            })
+        })
+    }
+}
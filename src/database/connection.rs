@@ -0,0 +1,97 @@
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ManageConnection, R2D2Connection};
+use diesel::sqlite::SqliteConnection;
+
+/// A connection to either backend, picked by the database URL. Queries both
+/// backends understand run on it directly, the rest match on the variant, see
+/// `each_backend!`.
+#[derive(diesel::MultiConnection)]
+pub enum DbConnection {
+    Postgres(PgConnection),
+    Sqlite(SqliteConnection),
+}
+
+/// Where the rows go, decided by the URL alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Postgres,
+    /// `sqlite://path`, or a path ending in `.db`, `.sqlite` or `.sqlite3`
+    Sqlite,
+}
+
+impl Backend {
+    pub fn from_url(url: &str) -> Backend {
+        let is_file = [".db", ".sqlite", ".sqlite3"].iter().any(|ext| url.ends_with(ext));
+
+        match url.starts_with("sqlite:") || url.starts_with("file:") || (is_file && !url.contains("://")) {
+            true => Backend::Sqlite,
+            false => Backend::Postgres,
+        }
+    }
+}
+
+/// Opens connections for the pool. Unlike diesel's manager it only tries the
+/// backend the URL names, so a Postgres outage reports Postgres' error rather
+/// than SQLite failing to open the URL as a file.
+#[derive(Debug)]
+pub struct DbManager {
+    url: String,
+    backend: Backend,
+    busy_timeout_ms: u64,
+}
+
+impl DbManager {
+    pub fn new(url: &str, busy_timeout_ms: u64) -> DbManager {
+        DbManager { url: url.to_string(), backend: Backend::from_url(url), busy_timeout_ms }
+    }
+
+    /// WAL lets readers, such as `query`, run next to a crawl, and the busy
+    /// timeout makes concurrent writers queue instead of failing.
+    fn open_sqlite(&self) -> ConnectionResult<SqliteConnection> {
+        let mut conn = SqliteConnection::establish(&self.url)?;
+
+        // the timeout first, switching to WAL has to wait for other connections
+        conn.batch_execute(&format!(
+            "pragma busy_timeout = {}; pragma journal_mode = wal; pragma synchronous = normal; pragma foreign_keys = on;",
+            self.busy_timeout_ms))
+            .map_err(|err| ConnectionError::BadConnection(err.to_string()))?;
+
+        Ok(conn)
+    }
+}
+
+impl ManageConnection for DbManager {
+    type Connection = DbConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<DbConnection, r2d2::Error> {
+        let conn = match self.backend {
+            Backend::Postgres => PgConnection::establish(&self.url).map(DbConnection::Postgres),
+            Backend::Sqlite => self.open_sqlite().map(DbConnection::Sqlite),
+        };
+
+        conn.map_err(r2d2::Error::ConnectionError)
+    }
+
+    fn is_valid(&self, conn: &mut DbConnection) -> Result<(), r2d2::Error> {
+        conn.ping().map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, conn: &mut DbConnection) -> bool {
+        std::thread::panicking() || conn.is_broken()
+    }
+}
+
+/// Runs the same query code against whichever connection `$conn` holds, for
+/// statements only the concrete backends support, like `ON CONFLICT`.
+macro_rules! each_backend {
+    ($conn:expr, |$inner:ident| $body:expr) => {
+        match $conn {
+            $crate::database::DbConnection::Postgres($inner) => $body,
+            $crate::database::DbConnection::Sqlite($inner) => $body,
+        }
+    };
+}
+
+pub(crate) use each_backend;
@@ -0,0 +1,411 @@
+use std::collections::HashSet;
+use chrono::{Days, NaiveDate};
+use diesel::prelude::*;
+use diesel::result::Error;
+
+use crate::{schema::{crawl_day, crawl_entry}, secweb::IndexEntry};
+use super::{each_backend, sql_types::{DbNow, UtcTime}, DbConnection, MAX_IDS};
+
+/// Progress of one day of daily index crawling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrawlStatus {
+    Pending,
+    InProgress,
+    Complete,
+    Failed,
+}
+
+impl CrawlStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CrawlStatus::Pending => "pending",
+            CrawlStatus::InProgress => "in_progress",
+            CrawlStatus::Complete => "complete",
+            CrawlStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> CrawlStatus {
+        match value {
+            "in_progress" => CrawlStatus::InProgress,
+            "complete" => CrawlStatus::Complete,
+            "failed" => CrawlStatus::Failed,
+            _ => CrawlStatus::Pending,
+        }
+    }
+}
+
+/// Progress of one index entry within a crawled day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryStatus {
+    Pending,
+    /// Every transaction of the filing is stored
+    Persisted,
+    Failed,
+}
+
+impl EntryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryStatus::Pending => "pending",
+            EntryStatus::Persisted => "persisted",
+            EntryStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Days that failed this often are left for someone to look at instead of
+/// being leased again.
+pub const MAX_ATTEMPTS: i32 = 3;
+
+/// Counts recorded when a day finishes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DayCounts {
+    pub entries: usize,
+    pub fetched: usize,
+    pub parsed: usize,
+    pub inserted: usize,
+    pub failed: usize,
+}
+
+pub fn day_status(conn: &mut DbConnection, date: NaiveDate) -> Result<Option<CrawlStatus>, Error> {
+    let status: Option<String> = crawl_day::table
+        .select(crawl_day::Status)
+        .filter(crawl_day::CrawlDate.eq(date))
+        .first(conn)
+        .optional()?;
+
+    Ok(status.as_deref().map(CrawlStatus::parse))
+}
+
+/// Registers days that are going to be crawled, leaving known days alone.
+pub fn plan_days(conn: &mut DbConnection, dates: &[NaiveDate]) -> Result<usize, Error> {
+    let rows: Vec<_> = dates.iter()
+        .map(|date| (crawl_day::CrawlDate.eq(*date), crawl_day::Status.eq(CrawlStatus::Pending.as_str())))
+        .collect();
+
+    each_backend!(conn, |conn| diesel::insert_into(crawl_day::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(conn))
+}
+
+pub fn start_day(conn: &mut DbConnection, date: NaiveDate) -> Result<usize, Error> {
+    let now = UtcTime::now();
+
+    each_backend!(conn, |conn| diesel::insert_into(crawl_day::table)
+        .values((
+            crawl_day::CrawlDate.eq(date),
+            crawl_day::Status.eq(CrawlStatus::InProgress.as_str()),
+            crawl_day::Attempts.eq(1),
+            crawl_day::StartedAt.eq(now),
+            crawl_day::UpdatedAt.eq(now)))
+        .on_conflict(crawl_day::CrawlDate)
+        .do_update()
+        .set((
+            crawl_day::Status.eq(CrawlStatus::InProgress.as_str()),
+            crawl_day::Attempts.eq(crawl_day::Attempts + 1),
+            crawl_day::Error.eq(None::<String>),
+            crawl_day::StartedAt.eq(now),
+            crawl_day::FinishedAt.eq(None::<UtcTime>),
+            crawl_day::UpdatedAt.eq(now)))
+        .execute(conn))
+}
+
+/// Records how a day went. With `leased_by` only while that worker still
+/// holds the day, a worker that lost its lease leaves the day to the new one.
+pub fn finish_day(
+    conn: &mut DbConnection,
+    date: NaiveDate,
+    status: CrawlStatus,
+    counts: DayCounts,
+    error: Option<&str>,
+    leased_by: Option<&str>) -> Result<usize, Error>
+{
+    let now = UtcTime::now();
+    let error: Option<String> = error.map(|e| e.chars().take(500).collect());
+
+    let day = crawl_day::table.filter(crawl_day::CrawlDate.eq(date));
+    let changes = (
+            crawl_day::Status.eq(status.as_str()),
+            crawl_day::Entries.eq(counts.entries as i32),
+            crawl_day::Fetched.eq(counts.fetched as i32),
+            crawl_day::Parsed.eq(counts.parsed as i32),
+            crawl_day::Inserted.eq(counts.inserted as i32),
+            crawl_day::Failed.eq(counts.failed as i32),
+            crawl_day::Error.eq(error),
+            crawl_day::FinishedAt.eq(now),
+            crawl_day::UpdatedAt.eq(now),
+            crawl_day::LeaseExpires.eq(None::<UtcTime>));
+
+    match leased_by {
+        Some(worker) => diesel::update(day.filter(crawl_day::LeasedBy.eq(worker)))
+            .set(changes)
+            .execute(conn),
+        None => diesel::update(day).set(changes).execute(conn),
+    }
+}
+
+/// Takes the oldest day that is pending, failed fewer than `MAX_ATTEMPTS` times
+/// or whose lease ran out, and leases it to `worker` for `lease_secs`. Days
+/// put off with `defer_day` wait until their time has come. Rows
+/// other workers are leasing at the same moment are skipped rather than waited
+/// for, and expiry uses the database clock so workers needn't agree on time.
+/// SQLite has no row locks, there the whole database is locked for the lease.
+pub fn lease_day(conn: &mut DbConnection, worker: &str, lease_secs: i32) -> Result<Option<NaiveDate>, Error> {
+    let due = crawl_day::LeaseExpires.is_null().or(crawl_day::LeaseExpires.lt(DbNow::now().nullable()));
+    let pending = crawl_day::Status.eq(CrawlStatus::Pending.as_str())
+        .and(due);
+    let retry = crawl_day::Status.eq(CrawlStatus::Failed.as_str())
+        .and(crawl_day::Attempts.lt(MAX_ATTEMPTS))
+        .and(due);
+    let expired = crawl_day::Status.eq(CrawlStatus::InProgress.as_str())
+        .and(crawl_day::LeaseExpires.lt(DbNow::now().nullable()));
+
+    let oldest = crawl_day::table
+        .select(crawl_day::CrawlDate)
+        .filter(pending.or(retry).or(expired.assume_not_null()))
+        .order(crawl_day::CrawlDate);
+
+    let lease = |date: NaiveDate| diesel::update(crawl_day::table.filter(crawl_day::CrawlDate.eq(date)))
+        .set((
+            crawl_day::Status.eq(CrawlStatus::InProgress.as_str()),
+            crawl_day::Attempts.eq(crawl_day::Attempts + 1),
+            crawl_day::Error.eq(None::<String>),
+            crawl_day::LeasedBy.eq(worker),
+            crawl_day::LeaseExpires.eq(DbNow::in_secs(lease_secs).nullable()),
+            crawl_day::StartedAt.eq(DbNow::now().nullable()),
+            crawl_day::FinishedAt.eq(None::<UtcTime>),
+            crawl_day::UpdatedAt.eq(DbNow::now())));
+
+    let date = match conn {
+        DbConnection::Postgres(conn) => conn.transaction(|conn| {
+            let date = oldest.for_update().skip_locked().first(conn).optional()?;
+            date.map(|date| lease(date).execute(conn).map(|_| date)).transpose()
+        }),
+        DbConnection::Sqlite(conn) => conn.immediate_transaction(|conn| {
+            let date = oldest.first(conn).optional()?;
+            date.map(|date| lease(date).execute(conn).map(|_| date)).transpose()
+        }),
+    }?;
+
+    Ok(date)
+}
+
+/// Extends `worker`'s lease on `date`, false when the lease was lost to
+/// another worker after expiring.
+pub fn renew_lease(conn: &mut DbConnection, date: NaiveDate, worker: &str, lease_secs: i32) -> Result<bool, Error> {
+    let renewed = diesel::update(crawl_day::table
+            .filter(crawl_day::CrawlDate.eq(date))
+            .filter(crawl_day::LeasedBy.eq(worker))
+            .filter(crawl_day::Status.eq(CrawlStatus::InProgress.as_str())))
+        .set((
+            crawl_day::LeaseExpires.eq(DbNow::in_secs(lease_secs).nullable()),
+            crawl_day::UpdatedAt.eq(DbNow::now())))
+        .execute(conn)?;
+
+    Ok(renewed > 0)
+}
+
+/// Keeps a day `worker` gave back from being leased again for `delay_secs`,
+/// reusing the lease expiry as the time it is due.
+pub fn defer_day(conn: &mut DbConnection, date: NaiveDate, worker: &str, delay_secs: i32) -> Result<usize, Error> {
+    diesel::update(crawl_day::table
+            .filter(crawl_day::CrawlDate.eq(date))
+            .filter(crawl_day::LeasedBy.eq(worker))
+            .filter(crawl_day::Status.ne(CrawlStatus::InProgress.as_str())))
+        .set(crawl_day::LeaseExpires.eq(DbNow::in_secs(delay_secs).nullable()))
+        .execute(conn)
+}
+
+/// Whether any day waits for a `defer_day` delay to pass.
+pub fn has_deferred_days(conn: &mut DbConnection) -> Result<bool, Error> {
+    let deferred = crawl_day::Status.eq(CrawlStatus::Pending.as_str())
+        .or(crawl_day::Status.eq(CrawlStatus::Failed.as_str()).and(crawl_day::Attempts.lt(MAX_ATTEMPTS)));
+
+    diesel::select(diesel::dsl::exists(crawl_day::table
+            .filter(deferred)
+            .filter(crawl_day::LeaseExpires.ge(DbNow::now().nullable()))))
+        .get_result(conn)
+}
+
+/// Where a forward crawl picks up after a restart: the day after the latest
+/// complete day, which is also the day that was in progress when it stopped.
+pub fn resume_date(conn: &mut DbConnection) -> Result<Option<NaiveDate>, Error> {
+    let last: Option<NaiveDate> = crawl_day::table
+        .select(diesel::dsl::max(crawl_day::CrawlDate))
+        .filter(crawl_day::Status.eq(CrawlStatus::Complete.as_str()))
+        .first(conn)?;
+
+    Ok(last.and_then(|date| date.checked_add_days(Days::new(1))))
+}
+
+/// Days before `date` that failed or never finished with their attempts so
+/// far, oldest first.
+pub fn unfinished_before(conn: &mut DbConnection, date: NaiveDate) -> Result<Vec<(NaiveDate, i32)>, Error> {
+    crawl_day::table
+        .select((crawl_day::CrawlDate, crawl_day::Attempts))
+        .filter(crawl_day::CrawlDate.lt(date))
+        .filter(crawl_day::Status.eq_any([CrawlStatus::InProgress.as_str(), CrawlStatus::Failed.as_str()]))
+        .order(crawl_day::CrawlDate)
+        .load(conn)
+}
+
+/// Registers a day's entries as pending, leaving entries seen before alone.
+pub fn plan_entries(conn: &mut DbConnection, date: NaiveDate, entries: &[IndexEntry]) -> Result<usize, Error> {
+    let rows: Vec<_> = entries.iter()
+        .map(|entry| (
+            crawl_entry::AccessNo.eq(entry.access_no()),
+            crawl_entry::CrawlDate.eq(date),
+            crawl_entry::FilePath.eq(&entry.filepath),
+            crawl_entry::FormType.eq(&entry.form_type)))
+        .collect();
+
+    // four binds per row, the bind parameter limit applies to the whole statement
+    let mut planned = 0;
+    for chunk in rows.chunks(MAX_IDS / 4) {
+        planned += each_backend!(conn, |conn| diesel::insert_into(crawl_entry::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn))?;
+    }
+
+    Ok(planned)
+}
+
+/// Accession numbers of the day's entries that are already stored.
+pub fn persisted_entries(conn: &mut DbConnection, date: NaiveDate) -> Result<HashSet<String>, Error> {
+    let found: Vec<String> = crawl_entry::table
+        .select(crawl_entry::AccessNo)
+        .filter(crawl_entry::CrawlDate.eq(date))
+        .filter(crawl_entry::Status.eq(EntryStatus::Persisted.as_str()))
+        .load(conn)?;
+
+    Ok(found.into_iter().collect())
+}
+
+pub fn mark_entry(conn: &mut DbConnection, access_no: &str, status: EntryStatus, error: Option<&str>) -> Result<usize, Error> {
+    mark_entries(conn, &[access_no.to_string()], status, error)
+}
+
+/// `mark_entry` for many entries, one statement per `MAX_IDS` of them.
+pub fn mark_entries(conn: &mut DbConnection, access_nos: &[String], status: EntryStatus, error: Option<&str>) -> Result<usize, Error> {
+    let error: Option<String> = error.map(|e| e.chars().take(500).collect());
+
+    let mut marked = 0;
+    for chunk in access_nos.chunks(MAX_IDS) {
+        marked += diesel::update(crawl_entry::table.filter(crawl_entry::AccessNo.eq_any(chunk)))
+            .set((
+                crawl_entry::Status.eq(status.as_str()),
+                crawl_entry::Attempts.eq(crawl_entry::Attempts + 1),
+                crawl_entry::Error.eq(&error),
+                crawl_entry::UpdatedAt.eq(UtcTime::now())))
+            .execute(conn)?;
+    }
+
+    Ok(marked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_connection;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn finish(conn: &mut DbConnection, date: NaiveDate, status: CrawlStatus, leased_by: Option<&str>) -> usize {
+        finish_day(conn, date, status, DayCounts::default(), None, leased_by).unwrap()
+    }
+
+    #[test]
+    fn resumes_after_the_latest_complete_day() {
+        let conn = &mut test_connection();
+        assert_eq!(resume_date(conn).unwrap(), None);
+
+        for date in [ymd(2023, 1, 17), ymd(2023, 1, 18), ymd(2023, 1, 19)] {
+            start_day(conn, date).unwrap();
+        }
+        finish(conn, ymd(2023, 1, 17), CrawlStatus::Complete, None);
+        finish(conn, ymd(2023, 1, 18), CrawlStatus::Failed, None);
+
+        // the day in progress when it stopped is crawled again
+        assert_eq!(resume_date(conn).unwrap(), Some(ymd(2023, 1, 18)));
+        assert_eq!(unfinished_before(conn, ymd(2023, 1, 20)).unwrap(), [(ymd(2023, 1, 18), 1), (ymd(2023, 1, 19), 1)]);
+
+        start_day(conn, ymd(2023, 1, 18)).unwrap();
+        assert_eq!(day_status(conn, ymd(2023, 1, 18)).unwrap(), Some(CrawlStatus::InProgress));
+        assert_eq!(unfinished_before(conn, ymd(2023, 1, 19)).unwrap(), [(ymd(2023, 1, 18), 2)]);
+    }
+
+    #[test]
+    fn leases_the_oldest_day_once() {
+        let conn = &mut test_connection();
+        plan_days(conn, &[ymd(2023, 1, 18), ymd(2023, 1, 17)]).unwrap();
+        // planning again leaves known days alone
+        finish(conn, ymd(2023, 1, 18), CrawlStatus::Complete, None);
+        assert_eq!(plan_days(conn, &[ymd(2023, 1, 18)]).unwrap(), 0);
+
+        assert_eq!(lease_day(conn, "a", 60).unwrap(), Some(ymd(2023, 1, 17)));
+        assert_eq!(lease_day(conn, "b", 60).unwrap(), None);
+
+        assert!(renew_lease(conn, ymd(2023, 1, 17), "a", 60).unwrap());
+        assert!(!renew_lease(conn, ymd(2023, 1, 17), "b", 60).unwrap());
+    }
+
+    #[test]
+    fn expired_leases_move_to_another_worker() {
+        let conn = &mut test_connection();
+        let date = ymd(2023, 1, 17);
+        plan_days(conn, &[date]).unwrap();
+
+        assert_eq!(lease_day(conn, "a", -1).unwrap(), Some(date));
+        assert_eq!(lease_day(conn, "b", 60).unwrap(), Some(date));
+
+        // the worker that lost the day neither renews nor finishes it
+        assert!(!renew_lease(conn, date, "a", 60).unwrap());
+        assert_eq!(finish(conn, date, CrawlStatus::Complete, Some("a")), 0);
+        assert_eq!(day_status(conn, date).unwrap(), Some(CrawlStatus::InProgress));
+
+        assert_eq!(finish(conn, date, CrawlStatus::Complete, Some("b")), 1);
+        assert_eq!(day_status(conn, date).unwrap(), Some(CrawlStatus::Complete));
+    }
+
+    #[test]
+    fn failed_days_are_retried_until_max_attempts() {
+        let conn = &mut test_connection();
+        let date = ymd(2023, 1, 17);
+        plan_days(conn, &[date]).unwrap();
+
+        for _ in 0..MAX_ATTEMPTS {
+            assert_eq!(lease_day(conn, "a", 60).unwrap(), Some(date));
+            finish(conn, date, CrawlStatus::Failed, Some("a"));
+        }
+
+        assert_eq!(lease_day(conn, "a", 60).unwrap(), None);
+        assert!(!has_deferred_days(conn).unwrap());
+    }
+
+    #[test]
+    fn deferred_days_wait_for_their_delay() {
+        let conn = &mut test_connection();
+        let (first, second) = (ymd(2023, 1, 17), ymd(2023, 1, 18));
+        plan_days(conn, &[first, second]).unwrap();
+
+        assert_eq!(lease_day(conn, "a", 60).unwrap(), Some(first));
+        finish(conn, first, CrawlStatus::Failed, Some("a"));
+        // only the worker that gave the day back defers it
+        assert_eq!(defer_day(conn, first, "b", 60).unwrap(), 0);
+        assert_eq!(defer_day(conn, first, "a", 60).unwrap(), 1);
+        assert!(has_deferred_days(conn).unwrap());
+
+        assert_eq!(lease_day(conn, "a", 60).unwrap(), Some(second));
+        assert_eq!(lease_day(conn, "a", 60).unwrap(), None);
+
+        // due again once the delay has passed
+        assert_eq!(defer_day(conn, first, "a", -1).unwrap(), 1);
+        assert!(!has_deferred_days(conn).unwrap());
+        assert_eq!(lease_day(conn, "b", 60).unwrap(), Some(first));
+    }
+}
@@ -0,0 +1,108 @@
+//! Database access for the crawlers. Diesel and r2d2 block, so queries are
+//! sent as jobs to a few dedicated threads, one per pooled connection, and the
+//! caller awaits the reply, or blocks on it from the pipeline's writer stage.
+//! Tasks fetching filings never wait on Postgres or a SQLite lock, and a
+//! burst of database work can't take over tokio's blocking threads the
+//! parsers run on. The pool itself is built on the first job, so connecting
+//! doesn't stall the runtime either. One-off commands like `import`, `export`
+//! and `reparse` stream through a connection of their own instead.
+
+use std::{
+    fmt::Display,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex, OnceLock},
+    thread};
+use tokio::sync::oneshot;
+use tracing::error;
+
+use crate::config::config;
+use super::{get_connection_pool, DbConnection, DbPool};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The process wide pool and the threads using it.
+pub struct Db {
+    pool: OnceLock<DbPool>,
+    jobs: mpsc::Sender<Job>,
+}
+
+static DB: OnceLock<Db> = OnceLock::new();
+
+/// Starts the database threads on first use, `database.pool_size` of them.
+pub fn db() -> &'static Db {
+    DB.get_or_init(|| {
+        let (jobs, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for i in 0..config().database.pool_size {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("db-{i}"))
+                .spawn(move || loop {
+                    let job = rx.lock().unwrap().recv();
+                    let Ok(job) = job else {
+                        break;
+                    };
+
+                    // the caller sees the dropped reply, the thread lives on
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("Database job panicked");
+                    }
+                })
+                .expect("Could not start database thread");
+        }
+
+        Db { pool: OnceLock::new(), jobs }
+    })
+}
+
+type Reply<T> = oneshot::Receiver<Result<T, String>>;
+
+impl Db {
+    /// The pool, connecting on first use.
+    fn pool(&self) -> &DbPool {
+        self.pool.get_or_init(get_connection_pool)
+    }
+
+    fn send<T, E, F>(&'static self, f: F) -> Result<Reply<T>, String>
+    where
+        T: Send + 'static,
+        E: Display,
+        F: FnOnce(&mut DbConnection) -> Result<T, E> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let result = self.pool().get()
+                .map_err(|e| e.to_string())
+                .and_then(|mut conn| f(&mut conn).map_err(|e| e.to_string()));
+            let _ = tx.send(result);
+        });
+
+        self.jobs.send(job).map_err(|_| "Database threads stopped".to_string())?;
+        Ok(rx)
+    }
+
+    /// Runs `f` on a pooled connection on one of the database threads.
+    pub async fn run<T, E, F>(&'static self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        E: Display,
+        F: FnOnce(&mut DbConnection) -> Result<T, E> + Send + 'static,
+    {
+        let rx = self.send(f)?;
+        rx.await.unwrap_or_else(|_| Err("Database job panicked".to_string()))
+    }
+
+    /// `run` for code already off the runtime, like sinks in the pipeline's
+    /// writer stage, blocking until the job is done. Never from a job itself,
+    /// with every database thread busy it would wait forever.
+    pub fn run_blocking<T, E, F>(&'static self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        E: Display,
+        F: FnOnce(&mut DbConnection) -> Result<T, E> + Send + 'static,
+    {
+        let rx = self.send(f)?;
+        rx.blocking_recv().unwrap_or_else(|_| Err("Database job panicked".to_string()))
+    }
+}
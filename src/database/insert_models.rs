@@ -2,8 +2,9 @@ use bigdecimal::{BigDecimal, FromPrimitive};
 use chrono::NaiveDate;
 use diesel::prelude::*;
 use crate::{schema::*};
+use super::sql_types::{IntVec, TextVec};
 
-use crate::secweb::models::FilingTransaction;
+use crate::secweb::{models::FilingTransaction, submissions::{FormerName, Submissions}, PARSER_VERSION};
 
 
 #[derive(Insertable)]
@@ -19,7 +20,7 @@ pub struct NewIssuer<'a> {
 }
 
 impl NewIssuer<'_> {
-    pub fn map(filing: &FilingTransaction) -> NewIssuer {
+    pub fn map(filing: &FilingTransaction) -> NewIssuer<'_> {
         NewIssuer { 
             issuer_name: &filing.company, 
             issuer_symbol: &filing.symbol, 
@@ -28,6 +29,64 @@ impl NewIssuer<'_> {
     }
 }
 
+/// Company details from the submissions API, applied to existing issuer rows.
+#[derive(AsChangeset)]
+#[diesel(table_name = issuer)]
+pub struct IssuerMetadata {
+    #[diesel(column_name = "Tickers")]
+    pub tickers: TextVec,
+
+    #[diesel(column_name = "Exchanges")]
+    pub exchanges: TextVec,
+
+    #[diesel(column_name = "Sic")]
+    pub sic: Option<String>,
+
+    #[diesel(column_name = "SicDescription")]
+    pub sic_description: Option<String>,
+}
+
+impl IssuerMetadata {
+    pub fn map(submissions: &Submissions) -> IssuerMetadata {
+        let non_empty = |value: &str| Some(value.to_string()).filter(|v| !v.is_empty());
+
+        IssuerMetadata {
+            tickers: TextVec(submissions.tickers.iter().map(|t| t.to_uppercase()).collect()),
+            // kept the same length as tickers
+            exchanges: TextVec(submissions.exchanges.iter().map(|e| e.clone().unwrap_or_default()).collect()),
+            sic: non_empty(&submissions.sic),
+            sic_description: non_empty(&submissions.sic_description),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = issuer_former_name)]
+pub struct NewFormerName {
+    #[diesel(column_name = "IssuerId")]
+    pub issuer_id: i32,
+
+    #[diesel(column_name = "Name")]
+    pub name: String,
+
+    #[diesel(column_name = "DateFrom")]
+    pub date_from: Option<NaiveDate>,
+
+    #[diesel(column_name = "DateTo")]
+    pub date_to: Option<NaiveDate>,
+}
+
+impl NewFormerName {
+    pub fn map(former: &FormerName, issuer_id: i32) -> NewFormerName {
+        NewFormerName {
+            issuer_id,
+            name: former.name.to_uppercase(),
+            date_from: former.date_from(),
+            date_to: former.date_to(),
+        }
+    }
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = individual)]
 pub struct NewIndividual<'a> {
@@ -44,7 +103,7 @@ pub struct NewIndividual<'a> {
 }
 
 impl NewIndividual<'_> {
-    pub fn map(filing: &FilingTransaction) -> NewIndividual {
+    pub fn map(filing: &FilingTransaction) -> NewIndividual<'_> {
         let split: Vec<_> = filing.owner.split(" ")
             .map(|c| c.to_string())
             .collect();
@@ -58,13 +117,13 @@ impl NewIndividual<'_> {
             let last_name = Some(split[0].clone());
             let first_name = Some(split[1..split.len()].join(" "));
     
-            return NewIndividual {
+            NewIndividual {
                 full_name: filing.owner.to_string(), 
                 cik: &filing.owner_cik, 
-                first_name: first_name, 
-                last_name: last_name };
+                first_name, 
+                last_name }
         } else {
-            return NewIndividual {
+            NewIndividual {
                 full_name: filing.owner.to_string(),
                 cik: &filing.owner_cik,
                 first_name: Option::None,
@@ -74,7 +133,7 @@ impl NewIndividual<'_> {
     }
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, AsChangeset)]
 #[diesel(table_name = form)]
 pub struct NewForm {
     #[diesel(column_name = "IssuerId")]
@@ -93,24 +152,30 @@ pub struct NewForm {
     pub web_url: String,
 
     #[diesel(column_name = "AccessNo")]
-    pub access_no: String
+    pub access_no: String,
+
+    #[diesel(column_name = "ParserVersion")]
+    pub parser_version: i32
 }
 
 impl NewForm {
     pub fn map(filing: &FilingTransaction, issuer_id: i32) -> NewForm {
         NewForm { 
-            issuer_id: issuer_id, 
+            issuer_id, 
             date_reported: filing.form_date, 
             form_type: filing.form_type.to_string(),
             txt_url: filing.form_url.to_string(),
             web_url: filing.web_url.to_string(),
-            access_no: filing.access_no.to_string()
+            access_no: filing.access_no.to_string(),
+            parser_version: PARSER_VERSION
         }
     }
 }
 
+// binds None as NULL rather than DEFAULT, which SQLite can't take in a
+// multi-row insert
 #[derive(Insertable)]
-#[diesel(table_name = non_deriv_transaction)]
+#[diesel(table_name = non_deriv_transaction, treat_none_as_default_value = false)]
 pub struct NewNonDerivTransaction {
     #[diesel(column_name = "DateReported")]
     pub date_reported: NaiveDate,
@@ -146,7 +211,10 @@ pub struct NewNonDerivTransaction {
     pub amount: BigDecimal,
 
     #[diesel(column_name = "Relationships")]
-    pub relationships: Vec<i32>
+    pub relationships: IntVec,
+
+    #[diesel(column_name = "ParserVersion")]
+    pub parser_version: i32
 }
 
 impl NewNonDerivTransaction {
@@ -156,15 +224,15 @@ impl NewNonDerivTransaction {
         issuer_id: i32, 
         individual_id: i32) -> NewNonDerivTransaction 
     {
-        let relationships = filing.relationship.iter()
+        let relationships = IntVec(filing.relationship.iter()
             .map(|r| *r as i32)
-            .collect();
+            .collect());
 
         NewNonDerivTransaction { 
             date_reported: filing.trans_date, 
-            form_id: form_id, 
-            issuer_id: issuer_id,
-            individual_id: individual_id, 
+            form_id, 
+            issuer_id,
+            individual_id, 
             action_code: Some(filing.action_code.clone()), 
             ownership_code: Some(filing.ownership_code.clone()), 
             transaction_code: Some(filing.trans_code.clone()), 
@@ -172,6 +240,7 @@ impl NewNonDerivTransaction {
             shares_traded: BigDecimal::from_f32(filing.shares_traded).unwrap(),
             avg_price: BigDecimal::from_f32(filing.avg_price).unwrap(), 
             amount: BigDecimal::from_f32(filing.amount).unwrap(), 
-            relationships: relationships }
+            relationships,
+            parser_version: PARSER_VERSION }
     }
 }
\ No newline at end of file
@@ -1,194 +1,293 @@
-use bigdecimal::{BigDecimal, FromPrimitive};
-use diesel::pg::PgConnection;
 use diesel::prelude::*;
-use diesel::r2d2::ConnectionManager;
+use diesel::upsert::excluded;
 use diesel::result::Error;
 use diesel::r2d2::Pool;
-use dotenvy::dotenv;
-use std::collections::HashMap;
-use std::env;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
-use crate::database::insert_models::{NewIndividual, NewIssuer, NewForm, NewNonDerivTransaction};
-use crate::database::query_models::Form;
-use crate::secweb::models::FilingTransaction;
+use crate::database::insert_models::{IssuerMetadata, NewFormerName, NewIndividual, NewIssuer, NewForm, NewNonDerivTransaction};
+use crate::config::config;
+use crate::metrics::count_rows;
+use crate::secweb::{models::FilingTransaction, submissions::Submissions};
 
 pub mod query_models;
 pub mod insert_models;
+pub mod view;
+pub mod crawl_state;
+pub mod rate_budget;
+pub mod connection;
+pub mod sql_types;
+pub mod bulk;
+pub mod executor;
 
-use self::query_models::{Issuer, Individual, NonDerivTransaction};
+pub use self::connection::{Backend, DbConnection, DbManager};
+pub use self::executor::{db, Db};
+pub(crate) use self::connection::each_backend;
 
-pub fn get_connection_pool() -> Pool<ConnectionManager<PgConnection>> {
-    dotenv().ok();
+pub type DbPool = Pool<DbManager>;
 
-    let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let manager = ConnectionManager::<PgConnection>::new(url);
+/// Ids bound in one `eq_any`, below SQLite's limit of 999 parameters per
+/// statement in older versions. Longer lists are queried in chunks.
+pub const MAX_IDS: usize = 900;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+/// The same schema for SQLite, kept in step with `MIGRATIONS`.
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+/// Applies the migrations the database hasn't seen yet, returning their versions.
+pub fn run_migrations(conn: &mut DbConnection) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let applied = match conn {
+        DbConnection::Postgres(conn) => conn.run_pending_migrations(MIGRATIONS)?,
+        DbConnection::Sqlite(conn) => conn.run_pending_migrations(SQLITE_MIGRATIONS)?,
+    };
+
+    Ok(applied.iter().map(|version| version.to_string()).collect())
+}
+
+/// A migrated in-memory SQLite database, gone when the connection drops.
+#[cfg(test)]
+pub(crate) fn test_connection() -> DbConnection {
+    let mut conn = DbConnection::Sqlite(SqliteConnection::establish(":memory:").unwrap());
+    run_migrations(&mut conn).unwrap();
+    conn
+}
+
+/// Pool sized by the `[database]` config section, on Postgres or SQLite
+/// depending on the URL. Connections are opened in the background and by
+/// `get`, so an unreachable database shows up as errors there rather than
+/// blocking or failing here.
+pub fn get_connection_pool() -> DbPool {
+    let database = &config().database;
+
+    let url = database.url.as_deref()
+        .expect("Set database.url in the config or DATABASE_URL to use a database");
+    let manager = DbManager::new(url, database.connect_timeout().as_millis() as u64);
 
     Pool::builder()
+        .max_size(database.pool_size)
+        .connection_timeout(database.connect_timeout())
         .test_on_check_out(true)
-        .build(manager)
-        .expect("Could not build connection pool")
+        .build_unchecked(manager)
 }
 
+/// Cloning shares the id caches.
+#[derive(Clone)]
 pub struct SqlHelper{
     issuers_cache: Arc<Mutex<HashMap<String, i32>>>,
-    form_cache: Arc<Mutex<HashMap<String, i64>>>,
     ind_cache: Arc<Mutex<HashMap<String, i32>>>
 }
 
+impl Default for SqlHelper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SqlHelper {
     pub fn new() -> SqlHelper {
         SqlHelper { 
             issuers_cache: Arc::new(Mutex::new(HashMap::new())) ,
-            form_cache: Arc::new(Mutex::new(HashMap::new())), 
             ind_cache: Arc::new(Mutex::new(HashMap::new())) }
     }
 
-    pub fn create_issuer(&mut self, conn: &mut PgConnection, filing: &FilingTransaction) -> Result<i32, Error> {
+    /// The issuer row of the filing's CIK, inserted if there is none. Two
+    /// writers inserting the same issuer meet on its unique index and both get
+    /// the one row.
+    fn upsert_issuer(&self, conn: &mut DbConnection, filing: &FilingTransaction) -> Result<i32, Error> {
         use super::schema::issuer::dsl::*;
-        
-        let new_issuer = NewIssuer::map(&filing);
-        
-        let cache = &mut self.issuers_cache.lock().unwrap();
-        let cache_item = cache.get(&new_issuer.cik.to_string());
-        
-        if cache_item.is_some()  {
-            return Ok(*cache_item.unwrap())
+
+        let new_issuer = NewIssuer::map(filing);
+
+        if let Some(id) = self.issuers_cache.lock().unwrap().get(new_issuer.cik) {
+            return Ok(*id);
         }
-    
-        let existing: Result<Issuer, Error> = issuer
-            .filter(cik.like(new_issuer.cik))
-            .first::<Issuer>(conn);
-        
-        match existing {
-            Ok(result) => {
-                cache.insert(result.cik, result.issuer_id);
-                return Ok(result.issuer_id);
-            },
-            Err(_) => {
-                let new_issuer: Result<Issuer, Error> = 
-                    diesel::insert_into(super::schema::issuer::table)
-                    .values(&new_issuer)
-                    .get_result(conn);
-
-                if !new_issuer.is_err() {
-                    let new_issuer = new_issuer.unwrap();
-                    cache.insert(new_issuer.cik, new_issuer.issuer_id);
-                    Ok(new_issuer.issuer_id)
-                } else {
-                    Err(new_issuer.unwrap_err())
-                }
-            }
+
+        let existing = issuer
+            .select(IssuerId)
+            .filter(cik.eq(new_issuer.cik))
+            .first::<i32>(conn)
+            .optional()?;
+
+        if let Some(id) = existing {
+            return Ok(id);
         }
 
+        // updating a column to itself makes RETURNING give the existing row
+        let id = each_backend!(conn, |conn| diesel::insert_into(issuer)
+            .values(&new_issuer)
+            .on_conflict((Symbol, cik))
+            .do_update()
+            .set(cik.eq(excluded(cik)))
+            .returning(IssuerId)
+            .get_result(conn))?;
+
+        count_rows("issuer", 1);
+        Ok(id)
     }
-    
-    pub fn create_individual(&mut self, conn: &mut PgConnection, filing: &FilingTransaction) -> Result<i32, Error> {
+
+    fn upsert_individual(&self, conn: &mut DbConnection, filing: &FilingTransaction) -> Result<i32, Error> {
         use super::schema::individual::dsl::*;
-        
-        let new_ind = NewIndividual::map(&filing);
-
-        let mut cache = self.ind_cache.lock().unwrap();
-        let cache_item = cache.get(&new_ind.cik.to_string());
-        
-        if cache_item.is_some()  {
-            return Ok(*cache_item.unwrap());
+
+        let new_ind = NewIndividual::map(filing);
+
+        if let Some(id) = self.ind_cache.lock().unwrap().get(new_ind.cik) {
+            return Ok(*id);
         }
 
-        let existing: Result<Individual, Error> = individual
-            .filter(cik.like(new_ind.cik))
-            .first::<Individual>(conn);
-        
-        match existing {
-            Ok(result) => {
-                cache.insert(result.cik, result.individual_id);
-                Ok(result.individual_id)
-            },
-            Err(_) => {
-                let new_ind: Result<Individual, Error> = 
-                    diesel::insert_into(super::schema::individual::table)
-                    .values(&new_ind)
-                    .get_result(conn);
-
-                if !new_ind.is_err() {
-                    let new_ind = new_ind.unwrap();
-                    cache.insert(new_ind.cik, new_ind.individual_id);
-                    Ok(new_ind.individual_id)
-                } else {
-                    Err(new_ind.unwrap_err())
-                }
-            }
+        let existing = individual
+            .select(IndividualId)
+            .filter(cik.eq(new_ind.cik))
+            .first::<i32>(conn)
+            .optional()?;
+
+        if let Some(id) = existing {
+            return Ok(id);
         }
+
+        let id = each_backend!(conn, |conn| diesel::insert_into(individual)
+            .values(&new_ind)
+            .on_conflict(cik)
+            .do_update()
+            .set(cik.eq(excluded(cik)))
+            .returning(IndividualId)
+            .get_result(conn))?;
+
+        count_rows("individual", 1);
+        Ok(id)
     }
-    
-    pub fn create_form(&self, conn: &mut PgConnection, filing: &FilingTransaction, issuer_id: i32) -> Result<i64, Error> {
-        use super::schema::form::dsl::*;
-        
-        let new_form = NewForm::map(&filing, issuer_id);
-
-        let cache = &mut self.form_cache.lock().unwrap();
-        let cache_item = cache.get(&new_form.access_no);
-        
-        if cache_item.is_some()  {
-            return Ok(*cache_item.unwrap());
-        }
 
-        let existing: Result<Form, Error> = form
-            .filter(AccessNo.like(&new_form.access_no))
-            .first::<Form>(conn);
-        
-        match existing {
-            Ok(result) => {
-                cache.insert(result.access_no, result.form_id);
-                Ok(result.form_id)
-            },
-            Err(_) => {
-                let new_form: Result<Form, Error> = 
-                    diesel::insert_into(super::schema::form::table)
-                    .values(&new_form)
-                    .get_result(conn);
-
-                    
-                if !new_form.is_err() {
-                    let new_form = new_form.unwrap();
-                    cache.insert(new_form.access_no, new_form.form_id);
-                    Ok(new_form.form_id)
-                } else {
-                    Err(new_form.unwrap_err())
-                }
-            }
+    /// Store one parsed filing in a single transaction, so it is either fully
+    /// stored or not at all. The form row and all of its transactions replace
+    /// what an earlier run stored under the accession number, and rows are
+    /// tagged with `parser_version`.
+    pub fn upsert_filing(&mut self, conn: &mut DbConnection, filings: &[FilingTransaction], parser_version: i32) -> Result<usize, Error> {
+        use super::schema::{form, non_deriv_transaction};
+
+        let first = match filings.first() {
+            Some(first) => first,
+            None => return Ok(0),
+        };
+
+        // every row is stored under the first one's form, issuer and owner
+        if let Some(other) = filings.iter().find(|trans| trans.access_no != first.access_no) {
+            return Err(Error::QueryBuilderError(
+                format!("Rows of {} and {} passed as one filing", first.access_no, other.access_no).into()));
         }
+
+        let (issuer_id, ind_id, inserted) = conn.transaction(|conn| {
+            let issuer_id = self.upsert_issuer(conn, first)?;
+            let ind_id = self.upsert_individual(conn, first)?;
+
+            let new_form = NewForm {
+                parser_version,
+                ..NewForm::map(first, issuer_id)
+            };
+            let form_id: i64 = each_backend!(conn, |conn| diesel::insert_into(form::table)
+                .values(&new_form)
+                .on_conflict(form::AccessNo)
+                .do_update()
+                .set(&new_form)
+                .returning(form::FormId)
+                .get_result(conn))?;
+
+            diesel::delete(non_deriv_transaction::table)
+                .filter(non_deriv_transaction::FormId.eq(form_id))
+                .execute(conn)?;
+
+            let transactions: Vec<_> = filings.iter()
+                .map(|trans| NewNonDerivTransaction {
+                    parser_version,
+                    ..NewNonDerivTransaction::map(trans, form_id, issuer_id, ind_id)
+                })
+                .collect();
+
+            let inserted = each_backend!(conn, |conn| diesel::insert_into(non_deriv_transaction::table)
+                .values(&transactions)
+                .execute(conn))?;
+
+            Ok::<_, Error>((issuer_id, ind_id, inserted))
+        })?;
+
+        // only committed ids, a rolled back insert must not be reused
+        self.issuers_cache.lock().unwrap().insert(first.company_cik.clone(), issuer_id);
+        self.ind_cache.lock().unwrap().insert(first.owner_cik.clone(), ind_id);
+
+        count_rows("form", 1);
+        count_rows("non_deriv_transaction", inserted);
+        Ok(inserted)
     }
-    
-    pub fn insert_nonderiv(conn: &mut PgConnection, filing: &FilingTransaction, form_id: i64, issuer_id: i32, ind_id: i32) -> Result<NonDerivTransaction, Error> {
-        use super::schema::non_deriv_transaction::dsl::*;
-        
-        let new_trans = NewNonDerivTransaction::map(filing, form_id, issuer_id, ind_id);
-        
-        let balance = BigDecimal::from_f32(filing.shares_owned).unwrap();
-        let existing = non_deriv_transaction
-            .filter(FormId.eq(form_id)
-            .and(DateReported.eq(filing.trans_date)
-            .and(SharesBalance.eq(balance))))
-            .first::<NonDerivTransaction>(conn);
-
-    match existing {
-        Ok(result) => {
-            Ok(result)
-        },
-        Err(_) => {
-            return diesel::insert_into(super::schema::non_deriv_transaction::table)
-                .values(&new_trans)
-                .get_result(conn);
+
+    /// Copies tickers, exchanges, SIC and former names from a submissions
+    /// document onto the issuer rows of its CIK, returning how many there were.
+    pub fn save_issuer_metadata(conn: &mut DbConnection, submissions: &Submissions) -> Result<usize, Error> {
+        use super::schema::{issuer, issuer_former_name};
+
+        let issuer_cik = format!("{:0>10}", submissions.cik.trim_start_matches('0'));
+        let metadata = IssuerMetadata::map(submissions);
+
+        conn.transaction(|conn| {
+            let ids: Vec<i32> = diesel::update(issuer::table)
+                .filter(issuer::cik.eq(&issuer_cik))
+                .set(&metadata)
+                .returning(issuer::IssuerId)
+                .get_results(conn)?;
+
+            let former_names: Vec<_> = ids.iter()
+                .flat_map(|id| submissions.former_names.iter().map(|f| NewFormerName::map(f, *id)))
+                .collect();
+
+            for name in &former_names {
+                each_backend!(conn, |conn| diesel::insert_into(issuer_former_name::table)
+                    .values(name)
+                    .on_conflict((issuer_former_name::IssuerId, issuer_former_name::Name))
+                    .do_update()
+                    .set((
+                        issuer_former_name::DateFrom.eq(name.date_from),
+                        issuer_former_name::DateTo.eq(name.date_to)))
+                    .execute(conn))?;
             }
-        }
+
+            Ok(ids.len())
+        })
     }
 
-    pub fn bulk_insert_nonderivs(conn: &mut PgConnection, transactions: &[NewNonDerivTransaction]) -> Result<usize, Error> {
-        use super::schema::non_deriv_transaction;
+    /// Deletes the transactions stored for `access_no`, for a filing that no
+    /// longer parses to any. With `issuer_cik` only when the filing is that
+    /// issuer's. Returns how many rows went.
+    pub fn delete_transactions(conn: &mut DbConnection, access_no: &str, issuer_cik: Option<&str>) -> Result<usize, Error> {
+        use super::schema::{form, issuer, non_deriv_transaction};
 
-        diesel::insert_into(non_deriv_transaction::table)
-            .values(transactions)
+        let forms: Vec<(i64, String)> = form::table
+            .inner_join(issuer::table)
+            .select((form::FormId, issuer::cik))
+            .filter(form::AccessNo.eq(access_no))
+            .load(conn)?;
+
+        let trim = |cik: &str| cik.trim().trim_start_matches('0').to_string();
+        let form_ids: Vec<i64> = forms.into_iter()
+            .filter(|(_, cik)| issuer_cik.is_none_or(|wanted| trim(wanted) == trim(cik)))
+            .map(|(id, _)| id)
+            .collect();
+
+        diesel::delete(non_deriv_transaction::table)
+            .filter(non_deriv_transaction::FormId.eq_any(form_ids))
             .execute(conn)
     }
-}
\ No newline at end of file
+
+    /// The subset of `access_nos` that already has a row in `form`.
+    pub fn known_access_nos(conn: &mut DbConnection, access_nos: &[String]) -> Result<HashSet<String>, Error> {
+        use super::schema::form::dsl::*;
+
+        let mut known = HashSet::new();
+        for chunk in access_nos.chunks(MAX_IDS) {
+            let found: Vec<String> = form
+                .select(AccessNo)
+                .filter(AccessNo.eq_any(chunk))
+                .load(conn)?;
+            known.extend(found);
+        }
+
+        Ok(known)
+    }
+}
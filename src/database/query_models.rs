@@ -1,6 +1,9 @@
 use chrono::NaiveDate;
 use diesel::prelude::*;
 use bigdecimal::BigDecimal;
+use serde::{Serialize, Serializer};
+
+use super::sql_types::{IntVec, TextVec};
 
 #[derive(Queryable, Debug)]
 pub struct Form {
@@ -10,7 +13,8 @@ pub struct Form {
     pub form_type: String,
     pub txt_url: String,
     pub access_no: String,
-    pub web_url: String
+    pub web_url: String,
+    pub parser_version: i32
 }
 
 #[derive(Queryable, Debug)]
@@ -27,7 +31,11 @@ pub struct Issuer {
     pub issuer_id: i32,
     pub name: String,
     pub symbol: String,
-    pub cik: String
+    pub cik: String,
+    pub tickers: TextVec,
+    pub exchanges: TextVec,
+    pub sic: Option<String>,
+    pub sic_description: Option<String>
 }
 
 #[derive(Queryable, Debug)]
@@ -44,5 +52,49 @@ pub struct NonDerivTransaction {
     pub shares_traded: BigDecimal,
     pub avg_price: BigDecimal,
     pub amount: BigDecimal,
-    pub relationships: Vec<i32>
-}
\ No newline at end of file
+    pub relationships: IntVec,
+    pub parser_version: i32
+}
+/// One transaction joined with its form, issuer and reporting owner, the shape
+/// of `query` output and exports.
+#[derive(Queryable, Serialize, Debug)]
+pub struct TransactionView {
+    pub transaction_id: i64,
+    pub access_no: String,
+    pub form_type: String,
+    pub period: NaiveDate,
+    pub trans_date: NaiveDate,
+    pub issuer_cik: String,
+    pub issuer_name: String,
+    pub symbol: String,
+    pub owner_cik: String,
+    pub owner_name: String,
+    #[serde(serialize_with = "relationship_names")]
+    pub relationships: IntVec,
+    pub transaction_code: Option<String>,
+    pub action_code: Option<String>,
+    pub ownership_code: Option<String>,
+    pub shares_traded: BigDecimal,
+    pub avg_price: BigDecimal,
+    pub amount: BigDecimal,
+    pub shares_balance: BigDecimal,
+    pub txt_url: String,
+    pub parser_version: i32
+}
+
+// flat text so the rows also fit in a CSV cell, e.g. DIRECTOR;OFFICER
+fn relationship_names<S: Serializer>(ids: &IntVec, serializer: S) -> Result<S::Ok, S::Error> {
+    let names: Vec<_> = ids.0.iter().copied().map(relationship_name).collect();
+
+    serializer.serialize_str(&names.join(";"))
+}
+
+/// The name of a `relationships` row, the ids are `Relationship` discriminants.
+pub fn relationship_name(id: i32) -> &'static str {
+    match id {
+        2 => "TEN PERCENT",
+        3 => "DIRECTOR",
+        4 => "OFFICER",
+        _ => "OTHER",
+    }
+}
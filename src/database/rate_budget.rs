@@ -0,0 +1,91 @@
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel::sql_types::Integer;
+
+use super::DbConnection;
+
+/// Takes `requests` from the budget of the current second, shared by every
+/// process using the database. Returns false when that would exceed `limit`,
+/// the caller should then try again in the next second. The window is taken
+/// from the database clock so workers needn't agree on time. More than
+/// `limit` requests never fit and are refused, take them in pieces instead.
+pub fn take_requests(conn: &mut DbConnection, requests: i32, limit: i32) -> Result<bool, Error> {
+    if requests > limit {
+        return Ok(false);
+    }
+
+    let window = match conn {
+        DbConnection::Postgres(_) => "date_trunc('second', now())",
+        DbConnection::Sqlite(_) => "strftime('%Y-%m-%d %H:%M:%S+00:00', 'now')",
+    };
+
+    // both read $1 and $2 as positional parameters
+    let taken = diesel::sql_query(format!(r#"
+        insert into request_budget ("Window", "Requests")
+        values ({window}, $1)
+        on conflict ("Window") do update
+            set "Requests" = request_budget."Requests" + excluded."Requests"
+            where request_budget."Requests" + excluded."Requests" <= $2"#))
+        .bind::<Integer, _>(requests)
+        .bind::<Integer, _>(limit)
+        .execute(conn)?;
+
+    Ok(taken > 0)
+}
+
+/// Drops windows older than a minute.
+pub fn prune(conn: &mut DbConnection) -> Result<usize, Error> {
+    let cutoff = match conn {
+        DbConnection::Postgres(_) => "now() - interval '1 minute'",
+        DbConnection::Sqlite(_) => "strftime('%Y-%m-%d %H:%M:%S+00:00', 'now', '-1 minute')",
+    };
+
+    diesel::sql_query(format!(r#"delete from request_budget where "Window" < {cutoff}"#))
+        .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+    use chrono::Utc;
+
+    use super::*;
+    use crate::database::test_connection;
+
+    /// Waits for the start of a second, so a test's requests share a window.
+    fn start_of_second() {
+        let millis = Utc::now().timestamp_subsec_millis();
+        if millis > 200 {
+            thread::sleep(Duration::from_millis(1_000 - millis as u64));
+        }
+    }
+
+    #[test]
+    fn requests_add_up_within_a_window() {
+        let conn = &mut test_connection();
+        start_of_second();
+
+        assert!(take_requests(conn, 4, 10).unwrap());
+        assert!(take_requests(conn, 6, 10).unwrap());
+        assert!(!take_requests(conn, 1, 10).unwrap());
+    }
+
+    #[test]
+    fn more_than_the_limit_is_refused() {
+        let conn = &mut test_connection();
+
+        // used to fit an empty window, going over the limit unnoticed
+        assert!(!take_requests(conn, 11, 10).unwrap());
+        assert!(take_requests(conn, 10, 10).unwrap());
+    }
+
+    #[test]
+    fn prune_keeps_the_current_window() {
+        let conn = &mut test_connection();
+        start_of_second();
+
+        assert!(take_requests(conn, 5, 10).unwrap());
+        assert_eq!(prune(conn).unwrap(), 0);
+        assert!(!take_requests(conn, 6, 10).unwrap());
+    }
+}
@@ -0,0 +1,204 @@
+//! Values of the column types Postgres and SQLite store differently. Postgres
+//! keeps its native arrays and `timestamptz`, SQLite has neither, so lists are
+//! stored as JSON text and timestamps as UTC text that sorts chronologically.
+//! Numeric columns need nothing here, SQLite keeps them as floating point,
+//! which holds the parsed `f32` values, and `view` rounds them to Postgres'
+//! three decimals on the way out.
+
+use chrono::{DateTime, Utc};
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::{is_aggregate, AppearsOnTable, AsExpression, Expression, SelectableExpression, ValidGrouping};
+use diesel::pg::Pg;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::{Array, HasSqlType, Integer, Text, Timestamptz, TimestamptzSqlite};
+use diesel::sqlite::Sqlite;
+use diesel::QueryResult;
+use serde::Serialize;
+
+use crate::schema::sql_types::{IntList, TextList, TimestampUtc};
+use super::connection::MultiBackend;
+
+/// The relationship ids of a transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = IntList)]
+#[serde(transparent)]
+pub struct IntVec(pub Vec<i32>);
+
+/// Tickers and exchanges of an issuer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = TextList)]
+#[serde(transparent)]
+pub struct TextVec(pub Vec<String>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = TimestampUtc)]
+#[serde(transparent)]
+pub struct UtcTime(pub DateTime<Utc>);
+
+impl UtcTime {
+    /// The local clock, see `DbNow` for the database's.
+    pub fn now() -> UtcTime {
+        UtcTime(Utc::now())
+    }
+}
+
+/// Lets the shared connection bind and read `$rust` as `$sql_type` by handing
+/// it to the backend it is connected to.
+macro_rules! multi_backend_type {
+    ($sql_type:ident, $rust:ident) => {
+        impl HasSqlType<$sql_type> for MultiBackend {
+            fn metadata(lookup: &mut Self::MetadataLookup) -> Self::TypeMetadata {
+                MultiBackend::lookup_sql_type::<$sql_type>(lookup)
+            }
+        }
+
+        impl ToSql<$sql_type, MultiBackend> for $rust {
+            fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, MultiBackend>) -> serialize::Result {
+                out.set_value(($sql_type, self));
+                Ok(IsNull::No)
+            }
+        }
+
+        impl FromSql<$sql_type, MultiBackend> for $rust {
+            fn from_sql(bytes: <MultiBackend as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+                bytes.from_sql::<Self, $sql_type>()
+            }
+        }
+    };
+}
+
+multi_backend_type!(IntList, IntVec);
+multi_backend_type!(TextList, TextVec);
+multi_backend_type!(TimestampUtc, UtcTime);
+
+impl ToSql<IntList, Pg> for IntVec {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        ToSql::<Array<Integer>, Pg>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<IntList, Pg> for IntVec {
+    fn from_sql(bytes: <Pg as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        FromSql::<Array<Integer>, Pg>::from_sql(bytes).map(IntVec)
+    }
+}
+
+impl ToSql<IntList, Sqlite> for IntVec {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(serde_json::to_string(&self.0)?);
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<IntList, Sqlite> for IntVec {
+    fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        let text = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Ok(IntVec(serde_json::from_str(&text)?))
+    }
+}
+
+impl ToSql<TextList, Pg> for TextVec {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        ToSql::<Array<Text>, Pg>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<TextList, Pg> for TextVec {
+    fn from_sql(bytes: <Pg as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        FromSql::<Array<Text>, Pg>::from_sql(bytes).map(TextVec)
+    }
+}
+
+impl ToSql<TextList, Sqlite> for TextVec {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(serde_json::to_string(&self.0)?);
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<TextList, Sqlite> for TextVec {
+    fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        let text = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Ok(TextVec(serde_json::from_str(&text)?))
+    }
+}
+
+impl ToSql<TimestampUtc, Pg> for UtcTime {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        ToSql::<Timestamptz, Pg>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<TimestampUtc, Pg> for UtcTime {
+    fn from_sql(bytes: <Pg as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        FromSql::<Timestamptz, Pg>::from_sql(bytes).map(UtcTime)
+    }
+}
+
+impl ToSql<TimestampUtc, Sqlite> for UtcTime {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        ToSql::<TimestamptzSqlite, Sqlite>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<TimestampUtc, Sqlite> for UtcTime {
+    fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        FromSql::<TimestamptzSqlite, Sqlite>::from_sql(bytes).map(UtcTime)
+    }
+}
+
+/// The database's clock, `secs` from now, so workers on different machines
+/// needn't agree on the time.
+#[derive(Debug, Clone, Copy, QueryId)]
+pub struct DbNow {
+    secs: i32,
+}
+
+impl DbNow {
+    pub fn now() -> DbNow {
+        DbNow { secs: 0 }
+    }
+
+    pub fn in_secs(secs: i32) -> DbNow {
+        DbNow { secs }
+    }
+}
+
+impl Expression for DbNow {
+    type SqlType = TimestampUtc;
+}
+
+impl<QS> AppearsOnTable<QS> for DbNow {}
+
+impl<QS> SelectableExpression<QS> for DbNow {}
+
+impl<GB> ValidGrouping<GB> for DbNow {
+    type IsAggregate = is_aggregate::Never;
+}
+
+impl QueryFragment<Pg> for DbNow {
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, Pg>) -> QueryResult<()> {
+        out.push_sql("(now() + ");
+        out.push_bind_param::<Integer, _>(&self.secs)?;
+        out.push_sql(" * interval '1 second')");
+        Ok(())
+    }
+}
+
+impl QueryFragment<Sqlite> for DbNow {
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, Sqlite>) -> QueryResult<()> {
+        // the layout diesel writes timestamps in, so both compare as text
+        out.push_sql("strftime('%Y-%m-%d %H:%M:%f+00:00', 'now', ");
+        out.push_bind_param::<Integer, _>(&self.secs)?;
+        out.push_sql(" || ' seconds')");
+        Ok(())
+    }
+}
+
+impl QueryFragment<MultiBackend> for DbNow {
+    fn walk_ast<'b>(&'b self, pass: AstPass<'_, 'b, MultiBackend>) -> QueryResult<()> {
+        MultiBackend::walk_variant_ast(self, pass)
+    }
+}
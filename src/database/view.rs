@@ -0,0 +1,130 @@
+use chrono::NaiveDate;
+use diesel::dsl::{count, max};
+use diesel::prelude::*;
+use diesel::result::Error;
+
+use super::{query_models::TransactionView, DbConnection};
+use crate::schema::{form, individual, issuer, non_deriv_transaction};
+
+/// Narrows the joined transaction view, every field is optional.
+#[derive(Debug, Default, Clone)]
+pub struct TransactionFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub issuer_cik: Option<String>,
+    pub symbol: Option<String>,
+    pub owner_cik: Option<String>,
+}
+
+/// Decimals of the numeric columns in Postgres.
+const NUMERIC_SCALE: i64 = 3;
+
+fn pad_cik(cik: &str) -> String {
+    format!("{:0>10}", cik.trim_start_matches('0'))
+}
+
+/// Applies a `TransactionFilter` to a boxed query over the joined view,
+/// whatever it selects.
+macro_rules! filter_view {
+    ($query:ident, $filter:expr) => {
+        if let Some(from) = $filter.from {
+            $query = $query.filter(non_deriv_transaction::DateReported.ge(from));
+        }
+
+        if let Some(to) = $filter.to {
+            $query = $query.filter(non_deriv_transaction::DateReported.le(to));
+        }
+
+        if let Some(cik) = &$filter.issuer_cik {
+            $query = $query.filter(issuer::cik.eq(pad_cik(cik)));
+        }
+
+        if let Some(symbol) = &$filter.symbol {
+            $query = $query.filter(issuer::Symbol.eq(symbol.to_uppercase()));
+        }
+
+        if let Some(cik) = &$filter.owner_cik {
+            $query = $query.filter(individual::cik.eq(pad_cik(cik)));
+        }
+    };
+}
+
+/// Up to `limit` transactions matching `filter` with an id above `after`,
+/// in id order so large exports can page through the table.
+pub fn load_transactions(
+    conn: &mut DbConnection,
+    filter: &TransactionFilter,
+    after: i64,
+    limit: i64) -> Result<Vec<TransactionView>, Error>
+{
+    let mut query = non_deriv_transaction::table
+        .inner_join(form::table)
+        .inner_join(issuer::table)
+        .inner_join(individual::table)
+        .select((
+            non_deriv_transaction::TransactionId,
+            form::AccessNo,
+            form::FormType,
+            form::DateReported,
+            non_deriv_transaction::DateReported,
+            issuer::cik,
+            issuer::Name,
+            issuer::Symbol,
+            individual::cik,
+            individual::FullName,
+            non_deriv_transaction::Relationships,
+            non_deriv_transaction::TransactionCode,
+            non_deriv_transaction::ActionCode,
+            non_deriv_transaction::OwnershipCode,
+            non_deriv_transaction::SharesTraded,
+            non_deriv_transaction::AvgPrice,
+            non_deriv_transaction::Amount,
+            non_deriv_transaction::SharesBalance,
+            form::TxtURL,
+            non_deriv_transaction::ParserVersion))
+        .filter(non_deriv_transaction::TransactionId.gt(after))
+        .order(non_deriv_transaction::TransactionId)
+        .limit(limit)
+        .into_boxed();
+
+    filter_view!(query, filter);
+
+    let mut rows: Vec<TransactionView> = query.load(conn)?;
+
+    // SQLite keeps numerics as floats, which read back with sixteen digits
+    if let DbConnection::Sqlite(_) = conn {
+        rows.iter_mut().for_each(round_numerics);
+    }
+
+    Ok(rows)
+}
+
+fn round_numerics(row: &mut TransactionView) {
+    for value in [&mut row.shares_traded, &mut row.avg_price, &mut row.amount, &mut row.shares_balance] {
+        *value = value.round(NUMERIC_SCALE).with_scale(NUMERIC_SCALE);
+    }
+}
+
+/// Transaction date, highest id and row count of every day with transactions
+/// matching `filter`, to tell which days changed without loading any rows.
+/// Rows get new ids whenever their form is stored again, so a day that kept
+/// its highest id and count is as it was.
+pub fn load_transaction_days(
+    conn: &mut DbConnection,
+    filter: &TransactionFilter) -> Result<Vec<(NaiveDate, Option<i64>, i64)>, Error>
+{
+    let mut query = non_deriv_transaction::table
+        .inner_join(form::table)
+        .inner_join(issuer::table)
+        .inner_join(individual::table)
+        .group_by(non_deriv_transaction::DateReported)
+        .select((
+            non_deriv_transaction::DateReported,
+            max(non_deriv_transaction::TransactionId),
+            count(non_deriv_transaction::TransactionId)))
+        .into_boxed();
+
+    filter_view!(query, filter);
+
+    query.load(conn)
+}
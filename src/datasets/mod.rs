@@ -0,0 +1,328 @@
+//! Importer for the SEC "Insider Transactions Data Sets", the quarterly zips
+//! (e.g. `2023q1_form345.zip`) holding every Form 3/4/5 as flattened TSV files.
+//! Only the tables we have a place for are read: SUBMISSION, REPORTINGOWNER
+//! and NONDERIV_TRANS.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+    io::Read,
+    path::Path};
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord};
+use tracing::{error, info};
+use zip::ZipArchive;
+
+/// `ParserVersion` of rows loaded from the data sets rather than parsed by us.
+pub const DATASET_VERSION: i32 = 0;
+
+use crate::{
+    secweb::{models::{FilingTransaction, Relationship}, archives_url, filing_index_url},
+    database::{bulk::{BulkLoader, BULK_ROWS}, get_connection_pool, DbConnection, SqlHelper}};
+
+struct Submission {
+    period: NaiveDate,
+    document_type: String,
+    issuer_cik: String,
+    issuer_name: String,
+    symbol: String,
+}
+
+struct Owner {
+    cik: String,
+    name: String,
+    relationships: Vec<Relationship>,
+}
+
+struct NonDerivRow {
+    trans_date: NaiveDate,
+    trans_code: String,
+    shares: f32,
+    price: f32,
+    action_code: String,
+    shares_owned: f32,
+    ownership_code: String,
+}
+
+/// Column positions by header name so the importer doesn't depend on the
+/// column order, which has changed between data set releases.
+struct Columns(HashMap<String, usize>);
+
+impl Columns {
+    fn new(headers: &StringRecord) -> Columns {
+        Columns(headers.iter()
+            .enumerate()
+            .map(|(i, h)| (h.trim().to_uppercase(), i))
+            .collect())
+    }
+
+    fn get<'a>(&self, record: &'a StringRecord, name: &str) -> &'a str {
+        self.0.get(name)
+            .and_then(|i| record.get(*i))
+            .unwrap_or_default()
+            .trim()
+    }
+
+    fn date(&self, record: &StringRecord, name: &str) -> Option<NaiveDate> {
+        // e.g. 17-JAN-2023
+        NaiveDate::parse_from_str(self.get(record, name), "%d-%b-%Y").ok()
+    }
+
+    fn num(&self, record: &StringRecord, name: &str) -> f32 {
+        self.get(record, name).parse::<f32>().unwrap_or(0.0)
+    }
+}
+
+// the crawler stores CIKs as they appear in the XML, zero padded to 10 digits
+fn pad_cik(cik: &str) -> String {
+    format!("{:0>10}", cik)
+}
+
+fn parse_relationships(text: &str) -> Vec<Relationship> {
+    let text = text.to_uppercase();
+    let mut relationships = Vec::new();
+
+    if text.contains("DIRECTOR") {
+        relationships.push(Relationship::DIRECTOR);
+    }
+
+    if text.contains("OFFICER") {
+        relationships.push(Relationship::OFFICER);
+    }
+
+    if text.contains("TENPERCENT") {
+        relationships.push(Relationship::TENPERC);
+    }
+
+    if text.contains("OTHER") {
+        relationships.push(Relationship::OTHER);
+    }
+
+    relationships
+}
+
+fn read_tsv<R: Read, F: FnMut(&Columns, &StringRecord)>(rdr: R, mut f: F) -> Result<(), csv::Error> {
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .quoting(false)
+        .flexible(true)
+        .from_reader(rdr);
+
+    let columns = Columns::new(rdr.headers()?);
+    for record in rdr.records() {
+        f(&columns, &record?);
+    }
+
+    Ok(())
+}
+
+pub struct InsiderDataset {
+    submissions: HashMap<String, Submission>,
+    owners: HashMap<String, Owner>,
+    transactions: HashMap<String, Vec<NonDerivRow>>,
+}
+
+impl InsiderDataset {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<InsiderDataset, Box<dyn Error>> {
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        let mut dataset = InsiderDataset {
+            submissions: HashMap::new(),
+            owners: HashMap::new(),
+            transactions: HashMap::new(),
+        };
+
+        read_tsv(Self::table(&mut zip, "SUBMISSION.tsv")?, |cols, rec| {
+            let Some(period) = cols.date(rec, "PERIOD_OF_REPORT") else {
+                return;
+            };
+
+            dataset.submissions.insert(cols.get(rec, "ACCESSION_NUMBER").to_string(), Submission {
+                period,
+                document_type: cols.get(rec, "DOCUMENT_TYPE").to_uppercase(),
+                issuer_cik: pad_cik(cols.get(rec, "ISSUERCIK")),
+                issuer_name: cols.get(rec, "ISSUERNAME").to_uppercase(),
+                symbol: cols.get(rec, "ISSUERTRADINGSYMBOL").to_uppercase(),
+            });
+        })?;
+
+        read_tsv(Self::table(&mut zip, "REPORTINGOWNER.tsv")?, |cols, rec| {
+            // like the XML parser, the first reporting owner is the one recorded
+            dataset.owners
+                .entry(cols.get(rec, "ACCESSION_NUMBER").to_string())
+                .or_insert_with(|| Owner {
+                    cik: pad_cik(cols.get(rec, "RPTOWNERCIK")),
+                    name: cols.get(rec, "RPTOWNERNAME").to_uppercase(),
+                    relationships: parse_relationships(cols.get(rec, "RPTOWNER_RELATIONSHIP")),
+                });
+        })?;
+
+        read_tsv(Self::table(&mut zip, "NONDERIV_TRANS.tsv")?, |cols, rec| {
+            let Some(trans_date) = cols.date(rec, "TRANS_DATE") else {
+                return;
+            };
+
+            dataset.transactions
+                .entry(cols.get(rec, "ACCESSION_NUMBER").to_string())
+                .or_default()
+                .push(NonDerivRow {
+                    trans_date,
+                    trans_code: cols.get(rec, "TRANS_CODE").to_uppercase(),
+                    shares: cols.num(rec, "TRANS_SHARES"),
+                    price: cols.num(rec, "TRANS_PRICEPERSHARE"),
+                    action_code: cols.get(rec, "TRANS_ACQUIRED_DISP_CD").to_uppercase(),
+                    shares_owned: cols.num(rec, "SHRS_OWND_FOLWNG_TRANS"),
+                    ownership_code: cols.get(rec, "DIRECT_INDIRECT_OWNERSHIP").to_uppercase(),
+                });
+        })?;
+
+        Ok(dataset)
+    }
+
+    fn table<'a>(zip: &'a mut ZipArchive<File>, name: &str) -> Result<zip::read::ZipFile<'a>, Box<dyn Error>> {
+        // some releases nest the tables in a folder named after the quarter
+        let path = zip.file_names()
+            .find(|n| n.rsplit('/').next().is_some_and(|f| f.eq_ignore_ascii_case(name)))
+            .map(str::to_string)
+            .ok_or_else(|| format!("{name} missing from data set"))?;
+
+        Ok(zip.by_name(&path)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.submissions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.submissions.is_empty()
+    }
+
+    pub fn access_nos(&self) -> Vec<String> {
+        self.submissions.keys().cloned().collect()
+    }
+
+    /// The transactions of one submission shaped like the crawler's output.
+    pub fn filing(&self, access_no: &str) -> Vec<FilingTransaction> {
+        let (Some(sub), Some(owner), Some(rows)) = (
+            self.submissions.get(access_no),
+            self.owners.get(access_no),
+            self.transactions.get(access_no)) else {
+            return Vec::new();
+        };
+
+        let form_url = archives_url(&format!("edgar/data/{}/{}.txt", sub.issuer_cik.trim_start_matches('0'), access_no));
+        let web_url = filing_index_url(&owner.cik, access_no);
+
+        rows.iter()
+            .map(|row| FilingTransaction {
+                trans_date: row.trans_date,
+                form_date: sub.period,
+                company: sub.issuer_name.clone(),
+                symbol: sub.symbol.clone(),
+                owner: owner.name.clone(),
+                relationship: owner.relationships.clone(),
+                shares_traded: row.shares,
+                avg_price: row.price,
+                amount: row.shares * row.price,
+                shares_owned: row.shares_owned,
+                trans_code: row.trans_code.clone(),
+                ownership_code: row.ownership_code.clone(),
+                action_code: row.action_code.clone(),
+                company_cik: sub.issuer_cik.clone(),
+                owner_cik: owner.cik.clone(),
+                form_type: sub.document_type.clone(),
+                form_url: form_url.clone(),
+                web_url: web_url.clone(),
+                access_no: access_no.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportStats {
+    pub submissions: usize,
+    pub imported: usize,
+    pub existing: usize,
+    pub empty: usize,
+    pub failed: usize,
+}
+
+/// Stores the filings `loader` holds, counting them as imported or failed.
+fn load_staged(conn: &mut DbConnection, loader: &mut BulkLoader, stats: &mut ImportStats) {
+    let filings = loader.filings();
+
+    match loader.load(conn) {
+        Ok(loaded) => {
+            for (access_no, err) in &loaded.failed {
+                error!(%access_no, %err, "Failed to import filing");
+            }
+            stats.imported += filings - loaded.failed.len();
+            stats.failed += loaded.failed.len();
+            info!(imported = stats.imported, submissions = stats.submissions, "Importing");
+        },
+        Err(err) => {
+            stats.failed += filings;
+            error!(filings, %err, "Failed to bulk load filings");
+        }
+    }
+}
+
+/// Loads one data set zip. Accession numbers the crawler already stored are
+/// left alone unless `replace` is set, in which case the data set wins. With
+/// `bulk` filings are loaded with `COPY` in batches of `BULK_ROWS` transactions.
+pub fn import<P: AsRef<Path>>(path: P, replace: bool, bulk: bool) -> Result<ImportStats, Box<dyn Error>> {
+    info!(path = %path.as_ref().display(), "Reading data set");
+    let dataset = InsiderDataset::open(path)?;
+
+    let pool = get_connection_pool();
+    let conn = &mut pool.get()?;
+    let mut helper = SqlHelper::new();
+    let mut loader = BulkLoader::new();
+
+    let mut access_nos = dataset.access_nos();
+    access_nos.sort();
+
+    let known = SqlHelper::known_access_nos(conn, &access_nos)?;
+    let mut stats = ImportStats { submissions: dataset.len(), ..Default::default() };
+
+    for access_no in &access_nos {
+        if !replace && known.contains(access_no) {
+            stats.existing += 1;
+            continue;
+        }
+
+        let filings = dataset.filing(access_no);
+        if filings.is_empty() {
+            stats.empty += 1;
+            continue;
+        }
+
+        if bulk {
+            loader.stage(&filings, DATASET_VERSION);
+            if loader.rows() >= BULK_ROWS {
+                load_staged(conn, &mut loader, &mut stats);
+            }
+            continue;
+        }
+
+        match helper.upsert_filing(conn, &filings, DATASET_VERSION) {
+            Ok(_) => {
+                stats.imported += 1;
+                if stats.imported.is_multiple_of(1000) {
+                    info!(imported = stats.imported, submissions = stats.submissions, "Importing");
+                }
+            },
+            Err(err) => {
+                stats.failed += 1;
+                error!(%access_no, %err, "Failed to import filing");
+            }
+        }
+    }
+
+    if !loader.is_empty() {
+        load_staged(conn, &mut loader, &mut stats);
+    }
+
+    Ok(stats)
+}
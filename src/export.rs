@@ -0,0 +1,375 @@
+//! Parquet export of the joined transaction view for notebooks and query
+//! engines. Files are laid out Hive style by transaction date, one per month,
+//! e.g. `year=2023/month=01/part.parquet`, and `_checkpoint.json` next to them
+//! keeps a watermark of every month written, its highest transaction id and
+//! row count, so the next run only rewrites the months whose watermark moved. Readers skip files starting with `_`
+//! or `.`, so neither the checkpoint nor unfinished files show up as data.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc};
+use arrow_array::{
+    builder::{ListBuilder, StringBuilder},
+    types::Date32Type,
+    ArrayRef, Date32Array, Decimal128Array, Int32Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::{Datelike, Months, NaiveDate};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::database::{
+    get_connection_pool,
+    DbConnection,
+    query_models::{relationship_name, TransactionView},
+    view::{load_transaction_days, load_transactions, TransactionFilter}};
+
+const CHECKPOINT: &str = "_checkpoint.json";
+const PART: &str = "part.parquet";
+const PAGE: i64 = 10_000;
+
+/// Same as the numeric(20, 3) columns in Postgres.
+const DECIMAL_PRECISION: u8 = 20;
+const DECIMAL_SCALE: i8 = 3;
+
+const ROW_GROUP: usize = 100_000;
+
+type Month = (i32, u32);
+
+/// Highest transaction id and row count of a month. Rows get new ids
+/// whenever their form is stored again, by a new parser version, a reparse or
+/// an import, so a month with new, re-stored or deleted forms moves either.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Watermark {
+    last_id: i64,
+    rows: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MonthStamp {
+    year: i32,
+    month: u32,
+    #[serde(flatten)]
+    watermark: Watermark,
+}
+
+/// Exported months. Checkpoints of older versions load empty, which
+/// rewrites everything.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    #[serde(default)]
+    months: Vec<MonthStamp>,
+}
+
+impl Checkpoint {
+    fn load(dir: &Path) -> Result<Checkpoint, Box<dyn Error>> {
+        match fs::read(dir.join(CHECKPOINT)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Checkpoint::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn watermarks(&self) -> BTreeMap<Month, Watermark> {
+        self.months.iter().map(|stamp| ((stamp.year, stamp.month), stamp.watermark)).collect()
+    }
+
+    fn save(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp = dir.join(format!(".{CHECKPOINT}.tmp"));
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(tmp, dir.join(CHECKPOINT))?;
+        Ok(())
+    }
+}
+
+fn schema() -> SchemaRef {
+    let decimal = DataType::Decimal128(DECIMAL_PRECISION, DECIMAL_SCALE);
+
+    Arc::new(Schema::new(vec![
+        Field::new("transaction_id", DataType::Int64, false),
+        Field::new("access_no", DataType::Utf8, false),
+        Field::new("form_type", DataType::Utf8, false),
+        Field::new("period", DataType::Date32, false),
+        Field::new("trans_date", DataType::Date32, false),
+        Field::new("issuer_cik", DataType::Utf8, false),
+        Field::new("issuer_name", DataType::Utf8, false),
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("owner_cik", DataType::Utf8, false),
+        Field::new("owner_name", DataType::Utf8, false),
+        Field::new_list("relationships", Field::new_list_field(DataType::Utf8, true), false),
+        Field::new("transaction_code", DataType::Utf8, true),
+        Field::new("action_code", DataType::Utf8, true),
+        Field::new("ownership_code", DataType::Utf8, true),
+        Field::new("shares_traded", decimal.clone(), false),
+        Field::new("avg_price", decimal.clone(), false),
+        Field::new("amount", decimal.clone(), false),
+        Field::new("shares_balance", decimal, false),
+        Field::new("txt_url", DataType::Utf8, false),
+        Field::new("parser_version", DataType::Int32, false),
+    ]))
+}
+
+fn unscaled(value: &BigDecimal) -> Result<i128, Box<dyn Error>> {
+    let (digits, _) = value.with_scale(DECIMAL_SCALE as i64).into_bigint_and_exponent();
+    digits.to_i128().ok_or_else(|| format!("{value} does not fit a decimal").into())
+}
+
+fn record_batch(schema: &SchemaRef, rows: &[&TransactionView]) -> Result<RecordBatch, Box<dyn Error>> {
+    let text = |f: fn(&TransactionView) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|row| f(row))))
+    };
+    let code = |f: fn(&TransactionView) -> Option<&str>| -> ArrayRef {
+        Arc::new(rows.iter().map(|row| f(row)).collect::<StringArray>())
+    };
+    let date = |f: fn(&TransactionView) -> chrono::NaiveDate| -> ArrayRef {
+        Arc::new(Date32Array::from_iter_values(rows.iter().map(|row| Date32Type::from_naive_date(f(row)))))
+    };
+    let decimal = |f: fn(&TransactionView) -> &BigDecimal| -> Result<ArrayRef, Box<dyn Error>> {
+        let values = rows.iter().map(|row| unscaled(f(row))).collect::<Result<Vec<_>, _>>()?;
+        Ok(Arc::new(Decimal128Array::from(values).with_precision_and_scale(DECIMAL_PRECISION, DECIMAL_SCALE)?))
+    };
+
+    let mut relationships = ListBuilder::new(StringBuilder::new());
+    for row in rows {
+        relationships.append_value(row.relationships.0.iter().map(|id| Some(relationship_name(*id))));
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(Int64Array::from_iter_values(rows.iter().map(|row| row.transaction_id))),
+        text(|row| &row.access_no),
+        text(|row| &row.form_type),
+        date(|row| row.period),
+        date(|row| row.trans_date),
+        text(|row| &row.issuer_cik),
+        text(|row| &row.issuer_name),
+        text(|row| &row.symbol),
+        text(|row| &row.owner_cik),
+        text(|row| &row.owner_name),
+        Arc::new(relationships.finish()),
+        code(|row| row.transaction_code.as_deref()),
+        code(|row| row.action_code.as_deref()),
+        code(|row| row.ownership_code.as_deref()),
+        decimal(|row| &row.shares_traded)?,
+        decimal(|row| &row.avg_price)?,
+        decimal(|row| &row.amount)?,
+        decimal(|row| &row.shares_balance)?,
+        text(|row| &row.txt_url),
+        Arc::new(Int32Array::from_iter_values(rows.iter().map(|row| row.parser_version))),
+    ];
+
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn month_dir(dir: &Path, (year, month): Month) -> PathBuf {
+    dir.join(format!("year={year}")).join(format!("month={month:02}"))
+}
+
+/// Months that have a directory under `dir`, whatever wrote them.
+fn months_on_disk(dir: &Path) -> Result<BTreeSet<Month>, Box<dyn Error>> {
+    let value = |path: &Path, key: &str| {
+        path.file_name()?.to_str()?.strip_prefix(key)?.parse().ok()
+    };
+
+    let mut months = BTreeSet::new();
+    for year in fs::read_dir(dir)? {
+        let year = year?.path();
+        let Some(y) = value(&year, "year=") else {
+            continue;
+        };
+
+        for month in fs::read_dir(&year)? {
+            if let Some(m) = value(&month?.path(), "month=") {
+                months.insert((y, m as u32));
+            }
+        }
+    }
+
+    Ok(months)
+}
+
+/// A month's new file, written under a hidden name until every month of the
+/// run is written.
+struct Partition {
+    tmp: PathBuf,
+    writer: ArrowWriter<File>,
+}
+
+impl Partition {
+    fn create(dir: &Path, month: Month, schema: &SchemaRef) -> Result<Partition, Box<dyn Error>> {
+        let dir = month_dir(dir, month);
+        fs::create_dir_all(&dir)?;
+
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROW_GROUP)
+            .build();
+
+        let tmp = dir.join(format!(".{PART}.tmp"));
+        let writer = ArrowWriter::try_new(File::create(&tmp)?, schema.clone(), Some(props))?;
+
+        Ok(Partition { tmp, writer })
+    }
+
+    /// Completes the file, returning where it is.
+    fn finish(self) -> Result<PathBuf, Box<dyn Error>> {
+        self.writer.close()?;
+        Ok(self.tmp)
+    }
+}
+
+/// Replaces whatever the month directory held with the file written at `tmp`,
+/// or with nothing when the month has no rows left. Files of older versions
+/// go too.
+fn replace_month(dir: &Path, tmp: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        let visible = path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(".parquet") && !name.starts_with(['.', '_']));
+
+        // renaming over the current file keeps it readable throughout
+        if visible && path.file_name() != Some(PART.as_ref()) {
+            fs::remove_file(path)?;
+        }
+    }
+
+    match tmp {
+        Some(tmp) => fs::rename(tmp, dir.join(PART))?,
+        None => match fs::remove_file(dir.join(PART)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        },
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParquetStats {
+    /// Months new, changed or gone since the last run
+    pub months: usize,
+    pub written: usize,
+    pub files: usize,
+}
+
+/// The watermarks of the months with transactions matching `filter` as
+/// they're stored now.
+fn current_months(conn: &mut DbConnection, filter: &TransactionFilter) -> Result<BTreeMap<Month, Watermark>, Box<dyn Error>> {
+    let mut months: BTreeMap<Month, Watermark> = BTreeMap::new();
+
+    for (day, last_id, rows) in load_transaction_days(conn, filter)? {
+        let watermark = months.entry((day.year(), day.month())).or_default();
+        watermark.last_id = watermark.last_id.max(last_id.unwrap_or_default());
+        watermark.rows += rows;
+    }
+
+    Ok(months)
+}
+
+/// `filter` narrowed to the days of `month`.
+fn month_filter(filter: &TransactionFilter, (year, month): Month) -> TransactionFilter {
+    let first = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+    let last = first.checked_add_months(Months::new(1)).unwrap().pred_opt().unwrap();
+
+    TransactionFilter {
+        from: Some(filter.from.map_or(first, |from| from.max(first))),
+        to: Some(filter.to.map_or(last, |to| to.min(last))),
+        ..filter.clone()
+    }
+}
+
+/// Writes all of `month`'s transactions matching `filter` to a new hidden
+/// file, returning it and how many there were. Nothing is written for an
+/// empty month.
+fn write_month(
+    conn: &mut DbConnection,
+    dir: &Path,
+    filter: &TransactionFilter,
+    month: Month,
+    schema: &SchemaRef) -> Result<Option<(PathBuf, usize)>, Box<dyn Error>>
+{
+    let filter = month_filter(filter, month);
+    let mut partition = None;
+    let mut written = 0;
+    let mut after = 0;
+
+    loop {
+        let rows = load_transactions(conn, &filter, after, PAGE)?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after = last.transaction_id;
+
+        let partition = match &mut partition {
+            Some(partition) => partition,
+            None => partition.insert(Partition::create(dir, month, schema)?),
+        };
+        let rows: Vec<_> = rows.iter().collect();
+        partition.writer.write(&record_batch(schema, &rows)?)?;
+        written += rows.len();
+    }
+
+    match partition {
+        Some(partition) => Ok(Some((partition.finish()?, written))),
+        None => Ok(None),
+    }
+}
+
+/// Brings the export in `dir` up to date with the transactions matching
+/// `filter`: every month whose watermark differs from the checkpoint's is
+/// written again in full, then the checkpoint moves
+/// up. A failed run leaves the checkpoint alone, so its rerun redoes the same
+/// months.
+pub fn export_parquet(dir: &Path, filter: &TransactionFilter) -> Result<ParquetStats, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let checkpoint = Checkpoint::load(dir)?;
+
+    let pool = get_connection_pool();
+    let conn = &mut pool.get()?;
+    let schema = schema();
+
+    let current = current_months(conn, filter)?;
+    let exported = checkpoint.watermarks();
+    let mut stats = ParquetStats::default();
+
+    // months on disk the checkpoint doesn't account for, e.g. from an older version
+    let mut months: BTreeSet<Month> = months_on_disk(dir)?.into_iter().filter(|month| !exported.contains_key(month)).collect();
+
+    let changed: BTreeSet<Month> = current.keys().chain(exported.keys())
+        .filter(|month| current.get(month) != exported.get(month))
+        .copied()
+        .collect();
+    stats.months = changed.len();
+    months.extend(changed);
+
+    let mut written = Vec::new();
+    for month in months {
+        let tmp = write_month(conn, dir, filter, month, &schema)?.map(|(tmp, rows)| {
+            stats.written += rows;
+            stats.files += 1;
+            tmp
+        });
+
+        info!(year = month.0, month = month.1, written = stats.written, "Exported month");
+        written.push((month_dir(dir, month), tmp));
+    }
+
+    // swapped in only once every month is written
+    for (month_dir, tmp) in written {
+        if month_dir.exists() {
+            replace_month(&month_dir, tmp.as_deref())?;
+        }
+    }
+
+    let months = current.into_iter()
+        .map(|((year, month), watermark)| MonthStamp { year, month, watermark })
+        .collect();
+    Checkpoint { months }.save(dir)?;
+
+    Ok(stats)
+}
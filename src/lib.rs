@@ -1,4 +1,10 @@
+pub mod archive;
+pub mod config;
 pub mod secweb;
 pub mod crawler;
 pub mod database;
+pub mod datasets;
+pub mod export;
+pub mod logging;
+pub mod metrics;
 pub mod  schema;
\ No newline at end of file
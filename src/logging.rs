@@ -0,0 +1,103 @@
+use std::io::{self, IsTerminal};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry};
+
+/// How log lines are written to stderr, which keeps stdout free for output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line with the fields of every enclosing span
+    Json,
+}
+
+/// Flushes exported spans when dropped, keep it alive until exit.
+pub struct LogGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("Could not flush spans: {err}");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber. Levels come from `RUST_LOG`, `info` by
+/// default. Built with the `otel` feature, spans are also exported over OTLP
+/// when `OTEL_EXPORTER_OTLP_ENDPOINT` is set, e.g. `http://localhost:4318`.
+pub fn init(format: LogFormat) -> LogGuard {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let output = match format {
+        LogFormat::Text => fmt::layer()
+            .with_writer(io::stderr)
+            .with_ansi(io::stderr().is_terminal())
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_writer(io::stderr)
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    #[cfg(feature = "otel")]
+    let provider = otel::provider();
+
+    #[cfg(feature = "otel")]
+    let export = provider.as_ref().map(otel::layer);
+    #[cfg(not(feature = "otel"))]
+    let export = None;
+
+    let layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> =
+        [Some(output), export].into_iter().flatten().collect();
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .init();
+
+    LogGuard {
+        #[cfg(feature = "otel")]
+        provider,
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use std::env;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::SpanExporter;
+    use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+    use tracing_subscriber::{Layer, Registry};
+
+    pub fn provider() -> Option<SdkTracerProvider> {
+        env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+
+        let exporter = match SpanExporter::builder().with_http().build() {
+            Ok(exporter) => exporter,
+            Err(err) => {
+                eprintln!("Could not create OTLP exporter: {err}");
+                return None;
+            }
+        };
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("spysec").build())
+            .build();
+
+        Some(provider)
+    }
+
+    pub fn layer(provider: &SdkTracerProvider) -> Box<dyn Layer<Registry> + Send + Sync> {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("spysec"))
+            .boxed()
+    }
+}
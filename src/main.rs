@@ -1,22 +1,682 @@
+use std::{
+    env,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    process::{self, ExitCode},
+    time::Duration};
 use chrono::NaiveDate;
 use chrono_tz::US::Eastern;
-use spysec::crawler::Crawler;
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use tracing::{error, info};
+use spysec::{
+    archive::Archive,
+    config::{self, config, Config},
+    datasets,
+    export::export_parquet,
+    logging::{self, LogFormat},
+    metrics,
+    crawler::{
+        Crawler, Direction, Sink,
+        company::CompanyCrawler,
+        live::LiveCrawler,
+        reparse::{Reparser, ReparseFilter},
+        budget::RateBudget,
+        retry::retry_failed,
+        shutdown::Shutdown,
+        tarball::FeedIngest,
+        worker::{Worker, DEFAULT_LEASE}},
+    database::{db, get_connection_pool, run_migrations, view::{load_transactions, TransactionFilter}},
+    secweb::Edgar};
 
-#[tokio::main]
-async fn main() {
-    let start = chrono::Utc::now()
-        .with_timezone(&Eastern)
-        .date_naive();
+/// Mine SEC.gov insider trading filings
+#[derive(Parser)]
+#[command(name = "spysec", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// TOML config file, SPYSEC_CONFIG or ./spysec.toml by default
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Serve Prometheus metrics at /metrics on this address, e.g. 0.0.0.0:9184
+    #[arg(long, global = true)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Log lines as plain text or one JSON object per line, levels come from RUST_LOG
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+#[derive(Args)]
+struct FetchArgs {
+    /// Form types to pick from the indexes, repeatable, edgar.form_types by default
+    #[arg(long = "form", value_name = "TYPE")]
+    form_types: Vec<String>,
+
+    /// Filings requested per second, edgar.requests_per_second by default
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=10))]
+    batch: Option<u8>,
+}
+
+impl FetchArgs {
+    fn form_types(&self) -> Vec<String> {
+        match self.form_types.is_empty() {
+            true => config().edgar.form_types.clone(),
+            false => self.form_types.iter().map(|t| t.to_uppercase()).collect(),
+        }
+    }
+
+    fn batch(&self) -> usize {
+        self.batch.unwrap_or(config().edgar.requests_per_second) as usize
+    }
+}
+
+#[derive(Args)]
+struct SinkArgs {
+    /// Where parsed filings are written, repeatable, crawl.sinks by default
+    #[arg(long = "sink", value_enum)]
+    sinks: Vec<Sink>,
+
+    /// Load database rows with COPY in large batches, for backfills
+    #[arg(long)]
+    bulk: bool,
+}
+
+impl SinkArgs {
+    fn sinks(&self) -> Vec<Sink> {
+        match self.sinks.is_empty() {
+            true => config().crawl.sinks.clone(),
+            false => self.sinks.clone(),
+        }
+    }
+}
+
+#[derive(Args)]
+struct FilterArgs {
+    /// First transaction date, YYYY-MM-DD
+    #[arg(long)]
+    from: Option<NaiveDate>,
+
+    /// Last transaction date, YYYY-MM-DD
+    #[arg(long)]
+    to: Option<NaiveDate>,
+
+    /// Issuer CIK
+    #[arg(long)]
+    issuer: Option<String>,
+
+    /// Issuer trading symbol
+    #[arg(long)]
+    symbol: Option<String>,
+
+    /// Reporting owner CIK
+    #[arg(long)]
+    owner: Option<String>,
+}
+
+impl FilterArgs {
+    fn filter(self) -> TransactionFilter {
+        TransactionFilter {
+            from: self.from,
+            to: self.to,
+            issuer_cik: self.issuer,
+            symbol: self.symbol,
+            owner_cik: self.owner,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    Json,
+    /// A directory of year/month partitions, rewriting only months that changed since the last run
+    Parquet,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Crawl daily indexes day by day, following new days when --to is left out
+    Crawl {
+        /// First day, by default the day after the last completed one or today.
+        /// Required when crawling backward
+        #[arg(long, required_if_eq("direction", "backward"))]
+        from: Option<NaiveDate>,
+
+        /// Last day
+        #[arg(long)]
+        to: Option<NaiveDate>,
+
+        #[arg(long, value_enum, default_value_t = Direction::Forward)]
+        direction: Direction,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+
+        #[command(flatten)]
+        sinks: SinkArgs,
+    },
+    /// Crawl each day once EDGAR publishes its index, until stopped
+    Daemon {
+        /// First day, by default the day after the last completed one or today
+        #[arg(long)]
+        from: Option<NaiveDate>,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+
+        #[command(flatten)]
+        sinks: SinkArgs,
+    },
+    /// Crawl a date range from the quarterly full indexes
+    Backfill {
+        #[arg(long)]
+        from: NaiveDate,
+
+        /// Last day, today by default
+        #[arg(long)]
+        to: Option<NaiveDate>,
+
+        #[arg(long, value_enum, default_value_t = Direction::Backward)]
+        direction: Direction,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+
+        #[command(flatten)]
+        sinks: SinkArgs,
+    },
+    /// Queue days in crawl_day for workers
+    Plan {
+        #[arg(long)]
+        from: NaiveDate,
+
+        /// Last day, yesterday by default
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+    /// Crawl days leased from crawl_day, alongside any number of other workers
+    Worker {
+        /// Name recorded on leased days, host name and process id by default
+        #[arg(long)]
+        id: Option<String>,
 
-    let end = NaiveDate::from_ymd_opt(2000, 1, 4).unwrap();
-    let mut crawler = Crawler::new(&start);
+        /// Seconds a leased day stays with a worker that stopped renewing it
+        #[arg(long, default_value_t = DEFAULT_LEASE.as_secs())]
+        lease: u64,
 
+        /// Requests per second shared by all workers, edgar.shared_requests_per_second by default
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=10))]
+        rate: Option<u8>,
+
+        /// Keep running and crawl new days as they are published
+        #[arg(long)]
+        follow: bool,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+
+        #[command(flatten)]
+        sinks: SinkArgs,
+    },
+    /// Poll the current filings feed
+    Live {
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+    /// Retry the filings listed in filings/failed.txt
+    RetryFailed {
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=10))]
+        batch: Option<u8>,
+    },
+    /// Rebuild rows from the raw filing archive
+    Reparse {
+        #[arg(long)]
+        from: Option<NaiveDate>,
+
+        #[arg(long)]
+        to: Option<NaiveDate>,
+
+        /// Form types to reparse, repeatable, edgar.form_types by default
+        #[arg(long = "form", value_name = "TYPE")]
+        form_types: Vec<String>,
+
+        /// Issuer CIK
+        #[arg(long)]
+        issuer: Option<String>,
+    },
+    /// Crawl the whole history of issuers or reporting owners
+    Company {
+        #[arg(required = true, value_name = "CIK")]
+        ciks: Vec<String>,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+    /// Ingest daily feed tarballs, downloaded by date or read from disk
+    Feed {
+        #[arg(long = "date", value_name = "YYYY-MM-DD")]
+        dates: Vec<NaiveDate>,
+
+        #[arg(value_name = "NC_TAR_GZ")]
+        paths: Vec<PathBuf>,
+
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+    /// Load SEC insider transaction data set zips
+    Import {
+        /// Overwrite filings the crawler already stored
+        #[arg(long)]
+        replace: bool,
+
+        /// Load with COPY in large batches
+        #[arg(long)]
+        bulk: bool,
+
+        #[arg(required = true, value_name = "ZIP")]
+        paths: Vec<PathBuf>,
+    },
+    /// Write stored transactions to a file, or a directory for parquet
+    Export {
+        #[arg(long, short)]
+        out: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Apply pending database migrations
+    Migrate,
+    /// Print stored transactions
+    Query {
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
+}
+
+fn today() -> NaiveDate {
+    chrono::Utc::now().with_timezone(&Eastern).date_naive()
+}
+
+fn yesterday() -> NaiveDate {
+    today().pred_opt().unwrap()
+}
+
+/// Non-zero when anything failed, after logging how much.
+fn failed_exit(failed_days: usize) -> ExitCode {
+    if failed_days == 0 {
+        return ExitCode::SUCCESS;
+    }
+
+    error!(failed_days, "Days failed, they are tried again when the crawl resumes");
+    ExitCode::FAILURE
+}
+
+fn open_archive() -> Archive {
+    Archive::open(&config().storage.archive_dir).expect("Failed to open raw filing archive")
+}
+
+async fn crawl(from: Option<NaiveDate>, to: Option<NaiveDate>, direction: Direction, fetch: FetchArgs, sinks: SinkArgs) -> usize {
+    let start = from.unwrap_or_else(today);
+    let mut crawler = Crawler::new(&start)
+        .with_range(start, to, direction)
+        .with_form_types(&fetch.form_types())
+        .with_sinks(&sinks.sinks())
+        .with_bulk_load(sinks.bulk)
+        .with_shutdown(Shutdown::listen());
+
+    // without --from carry on where the last run stopped
+    if from.is_none() {
+        crawler = crawler.resume().await;
+    }
+
+    crawler.crawl(fetch.batch()).await
+}
+
+const EXPORT_PAGE: i64 = 10_000;
+
+fn export(out: PathBuf, format: ExportFormat, filter: TransactionFilter) -> Result<usize, Box<dyn Error>> {
+    let pool = get_connection_pool();
+    let conn = &mut pool.get()?;
+    let file = BufWriter::new(File::create(&out)?);
+
+    let mut csv = None;
+    let mut json = None;
+    match format {
+        ExportFormat::Csv => csv = Some(csv::Writer::from_writer(file)),
+        ExportFormat::Json => {
+            let mut file = file;
+            file.write_all(b"[")?;
+            json = Some(file);
+        }
+        ExportFormat::Parquet => unreachable!("parquet is written by export_parquet"),
+    }
+
+    let mut written = 0;
+    let mut after = 0;
     loop {
-        if crawler.crawl_date == end {
-            println!("Stop date reached {:?}", end);
+        let rows = load_transactions(conn, &filter, after, EXPORT_PAGE)?;
+        let Some(last) = rows.last() else {
             break;
+        };
+        after = last.transaction_id;
+
+        for row in &rows {
+            if let Some(csv) = csv.as_mut() {
+                csv.serialize(row)?;
+            }
+
+            if let Some(json) = json.as_mut() {
+                if written > 0 {
+                    json.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut *json, row)?;
+            }
+
+            written += 1;
         }
 
-        crawler.run(8).await;
+        info!(written, "Exported page");
+    }
+
+    if let Some(mut csv) = csv {
+        csv.flush()?;
     }
+
+    if let Some(mut json) = json {
+        json.write_all(b"]")?;
+        json.flush()?;
+    }
+
+    Ok(written)
+}
+
+fn query(filter: TransactionFilter, limit: i64) -> Result<(), Box<dyn Error>> {
+    let pool = get_connection_pool();
+    let conn = &mut pool.get()?;
+    let rows = load_transactions(conn, &filter, 0, limit)?;
+
+    println!(
+        "{:<10} {:<6} {:<30} {:<4} {:<3} {:>14} {:>12} {:>16} {:>14}",
+        "Date", "Symbol", "Owner", "Code", "A/D", "Shares", "Price", "Amount", "Balance");
+
+    for row in rows {
+        println!(
+            "{:<10} {:<6} {:<30.30} {:<4} {:<3} {:>14} {:>12} {:>16} {:>14}",
+            row.trans_date,
+            row.symbol,
+            row.owner_name,
+            row.transaction_code.unwrap_or_default(),
+            row.action_code.unwrap_or_default(),
+            row.shares_traded.with_scale(0),
+            row.avg_price.with_scale(2),
+            row.amount.with_scale(2),
+            row.shares_balance.with_scale(0));
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let _log_guard = logging::init(cli.log_format);
+
+    let path = cli.config.or_else(|| env::var_os("SPYSEC_CONFIG").map(PathBuf::from));
+    match Config::load(path.as_deref()) {
+        Ok(loaded) => config::init(loaded),
+        Err(err) => {
+            error!("{err}");
+            process::exit(2);
+        }
+    }
+
+    if let Some(addr) = cli.metrics_addr {
+        metrics::serve(addr);
+    }
+
+    // commands exit non-zero when they fail as a whole or leave failed
+    // filings or days behind
+    let mut code = ExitCode::SUCCESS;
+    match cli.command {
+        Command::Crawl { from, to, direction, fetch, sinks } => {
+            let failed_days = crawl(from, to, direction, fetch, sinks).await;
+            code = failed_exit(failed_days);
+        },
+        Command::Daemon { from, fetch, sinks } => {
+            let failed_days = crawl(from, None, Direction::Forward, fetch, sinks).await;
+            code = failed_exit(failed_days);
+        },
+        Command::Backfill { from, to, direction, fetch, sinks } => {
+            let failed_days = Crawler::new(&from)
+                .with_range(from, Some(to.unwrap_or_else(today)), direction)
+                .with_form_types(&fetch.form_types())
+                .with_sinks(&sinks.sinks())
+                .with_bulk_load(sinks.bulk)
+                .with_shutdown(Shutdown::listen())
+                .backfill(fetch.batch())
+                .await;
+            code = failed_exit(failed_days);
+        },
+        Command::Plan { from, to } => {
+            let planned = Crawler::new(&from)
+                .with_range(from, Some(to.unwrap_or_else(yesterday)), Direction::Forward)
+                .plan_range()
+                .await;
+
+            info!(planned, "Planned new days");
+        },
+        Command::Worker { id, lease, rate, follow, fetch, sinks } => {
+            let bulk = sinks.bulk;
+            let sinks = sinks.sinks();
+            // the sinks may come from the config file, so clap can't check this
+            if !sinks.contains(&Sink::Database) {
+                let mut cli = Cli::command();
+                cli.build();
+                cli.find_subcommand_mut("worker")
+                    .expect("worker subcommand")
+                    .error(ErrorKind::ArgumentConflict, "workers lease days from the database, --sink must include database")
+                    .exit();
+            }
+
+            let rate = rate.unwrap_or(config().edgar.shared_requests_per_second);
+            let crawler = Crawler::new(&today())
+                .with_form_types(&fetch.form_types())
+                .with_sinks(&sinks)
+                .with_bulk_load(bulk)
+                .with_rate_budget(RateBudget::new(rate))
+                .with_shutdown(Shutdown::listen());
+
+            let mut worker = Worker::new(crawler).with_lease(Duration::from_secs(lease));
+            if let Some(id) = id {
+                worker = worker.with_id(&id);
+            }
+
+            let crawled = worker.run(fetch.batch(), follow).await;
+            info!(crawled, "Worker done");
+        },
+        Command::Live { fetch } => {
+            let given_up = LiveCrawler::new(Edgar::from_config(), open_archive().shared(), &fetch.form_types(), fetch.batch())
+                .with_shutdown(Shutdown::listen())
+                .run()
+                .await;
+
+            if given_up > 0 {
+                code = ExitCode::FAILURE;
+            }
+        },
+        Command::RetryFailed { batch } => {
+            let batch = batch.unwrap_or(config().edgar.requests_per_second) as usize;
+            match retry_failed(Edgar::from_config(), open_archive().shared(), batch).await {
+                Ok(stats) => {
+                    info!(
+                        fetched = stats.fetched, parsed = stats.parsed, inserted = stats.inserted, failed = stats.failed,
+                        "Retry done");
+                    if stats.failed > 0 {
+                        code = ExitCode::FAILURE;
+                    }
+                },
+                Err(err) => {
+                    error!(%err, "Retry failed");
+                    code = ExitCode::FAILURE;
+                },
+            }
+        },
+        Command::Reparse { from, to, form_types, issuer } => {
+            let filter = ReparseFilter {
+                from,
+                to,
+                form_types: match form_types.is_empty() {
+                    true => config().edgar.form_types.clone(),
+                    false => form_types.iter().map(|t| t.to_uppercase()).collect(),
+                },
+                issuer_cik: issuer,
+            };
+
+            let archive = open_archive();
+            let result = tokio::task::spawn_blocking(move || Reparser::new(archive, filter).run()).await;
+
+            match result {
+                Ok(Ok(stats)) if stats.failed == 0 => info!(?stats, "Reparse done"),
+                Ok(Ok(stats)) => {
+                    error!(?stats, "Reparse done with failed filings");
+                    code = ExitCode::FAILURE;
+                },
+                Ok(Err(err)) => {
+                    error!(%err, "Reparse failed");
+                    code = ExitCode::FAILURE;
+                },
+                Err(err) => {
+                    error!(%err, "Reparse panicked");
+                    code = ExitCode::FAILURE;
+                }
+            }
+        },
+        Command::Company { ciks, fetch } => {
+            let crawler = CompanyCrawler::new(Edgar::from_config(), open_archive().shared(), &fetch.form_types(), fetch.batch());
+            for cik in ciks {
+                match crawler.run(&cik).await {
+                    Ok(stats) => {
+                        info!(
+                            %cik, fetched = stats.fetched, parsed = stats.parsed, inserted = stats.inserted, failed = stats.failed,
+                            "Company done");
+                        if stats.failed > 0 {
+                            code = ExitCode::FAILURE;
+                        }
+                    },
+                    Err(err) => {
+                        error!(%cik, %err, "Company failed");
+                        code = ExitCode::FAILURE;
+                    }
+                }
+            }
+        },
+        Command::Feed { dates, paths, fetch } => {
+            let ingest = FeedIngest::new(Edgar::from_config(), open_archive().shared(), &fetch.form_types(), fetch.batch());
+
+            for date in dates {
+                match ingest.ingest_date(date).await {
+                    Ok(stats) => {
+                        info!(%date, parsed = stats.parsed, inserted = stats.inserted, failed = stats.failed, "Feed done");
+                        if stats.failed > 0 {
+                            code = ExitCode::FAILURE;
+                        }
+                    },
+                    Err(err) => {
+                        error!(%date, %err, "Feed failed");
+                        code = ExitCode::FAILURE;
+                    }
+                }
+            }
+
+            for path in paths {
+                match ingest.ingest_file(&path).await {
+                    Ok(stats) => {
+                        info!(path = %path.display(), parsed = stats.parsed, inserted = stats.inserted, failed = stats.failed, "Feed done");
+                        if stats.failed > 0 {
+                            code = ExitCode::FAILURE;
+                        }
+                    },
+                    Err(err) => {
+                        error!(path = %path.display(), %err, "Feed failed");
+                        code = ExitCode::FAILURE;
+                    }
+                }
+            }
+        },
+        Command::Import { replace, bulk, paths } => {
+            for path in paths {
+                let result = tokio::task::spawn_blocking(move || datasets::import(&path, replace, bulk).map_err(|e| e.to_string()))
+                    .await
+                    .unwrap();
+
+                match result {
+                    Ok(stats) => info!(?stats, "Import done"),
+                    Err(err) => {
+                        error!(%err, "Import failed");
+                        code = ExitCode::FAILURE;
+                    },
+                }
+            }
+        },
+        Command::Export { out, format: ExportFormat::Parquet, filter } => {
+            let result = tokio::task::spawn_blocking(move || export_parquet(&out, &filter.filter()).map_err(|e| e.to_string()))
+                .await
+                .unwrap();
+
+            match result {
+                Ok(stats) => info!(?stats, "Export done"),
+                Err(err) => {
+                    error!(%err, "Export failed");
+                    code = ExitCode::FAILURE;
+                },
+            }
+        },
+        Command::Export { out, format, filter } => {
+            let result = tokio::task::spawn_blocking(move || export(out, format, filter.filter()).map_err(|e| e.to_string()))
+                .await
+                .unwrap();
+
+            match result {
+                Ok(written) => info!(written, "Export done"),
+                Err(err) => {
+                    error!(%err, "Export failed");
+                    code = ExitCode::FAILURE;
+                },
+            }
+        },
+        Command::Migrate => {
+            match db().run(run_migrations).await {
+                Ok(applied) if applied.is_empty() => info!("Database is up to date"),
+                Ok(applied) => applied.iter().for_each(|version| info!(%version, "Applied migration")),
+                Err(err) => {
+                    error!(%err, "Migration failed");
+                    code = ExitCode::FAILURE;
+                },
+            }
+        },
+        Command::Query { filter, limit } => {
+            let filter = filter.filter();
+            let result = tokio::task::spawn_blocking(move || query(filter, limit).map_err(|e| e.to_string()))
+                .await
+                .unwrap();
+
+            if let Err(err) = result {
+                error!(%err, "Query failed");
+                code = ExitCode::FAILURE;
+            }
+        },
+    }
+
+    code
 }
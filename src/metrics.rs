@@ -0,0 +1,136 @@
+use std::{convert::Infallible, net::SocketAddr, sync::OnceLock, time::Duration};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use tracing::{error, info};
+
+/// Counters and histograms describing crawler and database activity, served
+/// in the Prometheus text format by `serve`.
+pub struct Metrics {
+    registry: Registry,
+    /// Daily and quarterly index requests by kind and result
+    pub index_fetches: IntCounterVec,
+    /// Filings by pipeline stage reached: fetched, parsed, stored
+    pub filings: IntCounterVec,
+    /// Filings that failed, by error class
+    pub filing_failures: IntCounterVec,
+    pub rows_inserted: IntCounterVec,
+    pub http_requests: IntCounterVec,
+    pub http_duration: HistogramVec,
+    /// Time spent waiting on the local rate limit or the shared budget
+    pub rate_limit_wait: HistogramVec,
+    /// Days between the crawl date and yesterday, 0 once caught up
+    pub crawl_lag_days: IntGauge,
+    /// Unix time the last day was completed
+    pub last_day_completed: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("spysec".to_string()), None)
+            .expect("Invalid metrics prefix");
+
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let metric = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+            registry.register(Box::new(metric.clone())).unwrap();
+            metric
+        };
+
+        let histogram = |name: &str, help: &str, labels: &[&str], buckets: Vec<f64>| {
+            let metric = HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels).unwrap();
+            registry.register(Box::new(metric.clone())).unwrap();
+            metric
+        };
+
+        let gauge = |name: &str, help: &str| {
+            let metric = IntGauge::new(name, help).unwrap();
+            registry.register(Box::new(metric.clone())).unwrap();
+            metric
+        };
+
+        Metrics {
+            index_fetches: counter("index_fetches_total", "Index requests", &["kind", "result"]),
+            filings: counter("filings_total", "Filings by pipeline stage reached", &["stage"]),
+            filing_failures: counter("filing_failures_total", "Filings that failed by error class", &["class"]),
+            rows_inserted: counter("rows_inserted_total", "Rows inserted by table", &["table"]),
+            http_requests: counter("http_requests_total", "EDGAR requests by kind and status", &["kind", "status"]),
+            http_duration: histogram(
+                "http_request_duration_seconds", "EDGAR request latency", &["kind"],
+                vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+            rate_limit_wait: histogram(
+                "rate_limit_wait_seconds", "Time waited for the rate limit", &["limiter"],
+                vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0]),
+            crawl_lag_days: gauge("crawl_lag_days", "Days the crawl date is behind yesterday"),
+            last_day_completed: gauge("last_day_completed_timestamp_seconds", "When the last crawl day was completed"),
+            registry,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut body)
+            .expect("Failed to encode metrics");
+
+        body
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+pub fn observe_request(kind: &str, status: &str, elapsed: Duration) {
+    let metrics = metrics();
+    metrics.http_requests.with_label_values(&[kind, status]).inc();
+    metrics.http_duration.with_label_values(&[kind]).observe(elapsed.as_secs_f64());
+}
+
+pub fn count_index(kind: &str, result: &str) {
+    metrics().index_fetches.with_label_values(&[kind, result]).inc();
+}
+
+pub fn count_filing(stage: &str) {
+    metrics().filings.with_label_values(&[stage]).inc();
+}
+
+pub fn count_failure(class: &str) {
+    metrics().filing_failures.with_label_values(&[class]).inc();
+}
+
+pub fn count_rows(table: &str, rows: usize) {
+    metrics().rows_inserted.with_label_values(&[table]).inc_by(rows as u64);
+}
+
+pub fn observe_wait(limiter: &str, waited: Duration) {
+    metrics().rate_limit_wait.with_label_values(&[limiter]).observe(waited.as_secs_f64());
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", TextEncoder::new().format_type())
+            .body(Body::from(metrics().encode())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+
+    Ok(res.unwrap())
+}
+
+/// Serves `/metrics` on `addr` in the background.
+pub fn serve(addr: SocketAddr) {
+    let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+    let server = Server::bind(&addr).serve(make_svc);
+
+    info!("Serving metrics on http://{addr}/metrics");
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            error!(%err, "Metrics server error");
+        }
+    });
+}
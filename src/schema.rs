@@ -1,4 +1,64 @@
-// @generated automatically by Diesel CLI.
+// Generated by Diesel CLI from Postgres, then edited to be shared with SQLite:
+// array and timestamp columns use the types in `sql_types`, which each backend
+// stores its own way, see src/database/sql_types.rs.
+
+pub mod sql_types {
+    /// `integer[]` in Postgres, a JSON array in SQLite
+    #[derive(diesel::query_builder::QueryId, std::fmt::Debug, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(oid = 1007, array_oid = 0))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct IntList;
+
+    /// `varchar[]` in Postgres, a JSON array in SQLite
+    #[derive(diesel::query_builder::QueryId, std::fmt::Debug, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(oid = 1015, array_oid = 0))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct TextList;
+
+    /// `timestamp with time zone` in Postgres, UTC text in SQLite
+    #[derive(diesel::query_builder::QueryId, std::fmt::Debug, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(oid = 1184, array_oid = 1185))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct TimestampUtc;
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TimestampUtc;
+
+    crawl_day (CrawlDate) {
+        CrawlDate -> Date,
+        Status -> Varchar,
+        Entries -> Int4,
+        Fetched -> Int4,
+        Parsed -> Int4,
+        Inserted -> Int4,
+        Failed -> Int4,
+        Attempts -> Int4,
+        Error -> Nullable<Varchar>,
+        StartedAt -> Nullable<TimestampUtc>,
+        FinishedAt -> Nullable<TimestampUtc>,
+        UpdatedAt -> TimestampUtc,
+        LeasedBy -> Nullable<Varchar>,
+        LeaseExpires -> Nullable<TimestampUtc>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TimestampUtc;
+
+    crawl_entry (AccessNo) {
+        AccessNo -> Varchar,
+        CrawlDate -> Date,
+        FilePath -> Varchar,
+        FormType -> Varchar,
+        Status -> Varchar,
+        Attempts -> Int4,
+        Error -> Nullable<Varchar>,
+        UpdatedAt -> TimestampUtc,
+    }
+}
 
 diesel::table! {
     form (FormId) {
@@ -9,6 +69,7 @@ diesel::table! {
         TxtURL -> Varchar,
         AccessNo -> Varchar,
         WebURL -> Varchar,
+        ParserVersion -> Int4,
     }
 }
 
@@ -23,15 +84,34 @@ diesel::table! {
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TextList;
+
     issuer (IssuerId) {
         IssuerId -> Int4,
         Name -> Varchar,
         Symbol -> Varchar,
         cik -> Varchar,
+        Tickers -> TextList,
+        Exchanges -> TextList,
+        Sic -> Nullable<Varchar>,
+        SicDescription -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    issuer_former_name (IssuerId, Name) {
+        IssuerId -> Int4,
+        Name -> Varchar,
+        DateFrom -> Nullable<Date>,
+        DateTo -> Nullable<Date>,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::IntList;
+
     non_deriv_transaction (TransactionId) {
         TransactionId -> Int8,
         DateReported -> Date,
@@ -45,18 +125,34 @@ diesel::table! {
         SharesTraded -> Numeric,
         AvgPrice -> Numeric,
         Amount -> Numeric,
-        Relationships -> Array<Int4>,
+        Relationships -> IntList,
+        ParserVersion -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TimestampUtc;
+
+    request_budget (Window) {
+        Window -> TimestampUtc,
+        Requests -> Int4,
     }
 }
 
 diesel::joinable!(form -> issuer (IssuerId));
+diesel::joinable!(issuer_former_name -> issuer (IssuerId));
 diesel::joinable!(non_deriv_transaction -> form (FormId));
 diesel::joinable!(non_deriv_transaction -> individual (IndividualId));
 diesel::joinable!(non_deriv_transaction -> issuer (IssuerId));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    crawl_day,
+    crawl_entry,
     form,
     individual,
     issuer,
+    issuer_former_name,
     non_deriv_transaction,
+    request_budget,
 );
@@ -0,0 +1,134 @@
+use chrono::{Datelike, Days, NaiveDate, Weekday};
+
+/// Days EDGAR was closed outside the regular holiday rules: national days of
+/// mourning, Hurricane Sandy and the Christmas Eves and December 26ths federal
+/// offices got off by executive order.
+const SPECIAL_CLOSURES: &[(i32, u32, u32)] = &[
+    (1994, 4, 27),
+    (2001, 12, 24),
+    (2003, 12, 26),
+    (2004, 6, 11),
+    (2007, 1, 2),
+    (2007, 12, 24),
+    (2008, 12, 26),
+    (2012, 10, 29),
+    (2012, 10, 30),
+    (2012, 12, 24),
+    (2014, 12, 26),
+    (2018, 12, 5),
+    (2018, 12, 24),
+    (2019, 12, 24),
+    (2020, 12, 24),
+    (2024, 12, 24),
+    (2025, 1, 9),
+    (2025, 12, 24),
+    (2025, 12, 26),
+];
+
+fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+/// `n`th `weekday` of the month, counting from 1.
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u32) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n as u8).unwrap()
+}
+
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    let mut date = nth_weekday(year, month, weekday, 4);
+    while let Some(next) = date.checked_add_days(Days::new(7)).filter(|d| d.month() == month) {
+        date = next;
+    }
+
+    date
+}
+
+/// Fixed date holidays falling on a weekend are observed on the closest
+/// weekday, Friday for Saturday and Monday for Sunday.
+fn observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date.pred_opt().unwrap(),
+        Weekday::Sun => date.succ_opt().unwrap(),
+        _ => date,
+    }
+}
+
+/// Federal holidays observed in `year` as EDGAR closes for them. New Year's Day
+/// on a Saturday is observed on December 31st of the year before.
+pub fn holidays(year: i32) -> Vec<NaiveDate> {
+    let mut days = vec![
+        observed(ymd(year, 1, 1)),
+        nth_weekday(year, 2, Weekday::Mon, 3),
+        last_weekday(year, 5, Weekday::Mon),
+        observed(ymd(year, 7, 4)),
+        nth_weekday(year, 9, Weekday::Mon, 1),
+        nth_weekday(year, 10, Weekday::Mon, 2),
+        observed(ymd(year, 11, 11)),
+        nth_weekday(year, 11, Weekday::Thu, 4),
+        observed(ymd(year, 12, 25)),
+    ];
+
+    if year >= 1986 {
+        days.push(nth_weekday(year, 1, Weekday::Mon, 3));
+    }
+
+    if year >= 2021 {
+        days.push(observed(ymd(year, 6, 19)));
+    }
+
+    days.sort();
+    days
+}
+
+pub fn is_holiday(date: NaiveDate) -> bool {
+    let special = SPECIAL_CLOSURES.iter().any(|&(y, m, d)| ymd(y, m, d) == date);
+
+    special
+        || holidays(date.year()).contains(&date)
+        // December 31st standing in for a Saturday New Year's Day
+        || (date.month() == 12 && holidays(date.year() + 1).contains(&date))
+}
+
+/// Whether EDGAR accepts filings and publishes a daily index on `date`.
+pub fn is_business_day(date: NaiveDate) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !is_holiday(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weekend_holidays_move_to_the_closest_weekday() {
+        // July 4th on a Saturday and on a Sunday
+        assert!(!is_business_day(ymd(2020, 7, 3)));
+        assert!(is_business_day(ymd(2020, 7, 6)));
+        assert!(!is_business_day(ymd(2021, 7, 5)));
+        assert!(is_business_day(ymd(2021, 7, 2)));
+
+        // New Year's Day on a Saturday, observed the year before
+        assert!(!is_business_day(ymd(2021, 12, 31)));
+        assert!(is_business_day(ymd(2022, 1, 3)));
+    }
+
+    #[test]
+    fn good_friday_is_a_business_day() {
+        // markets close, federal offices and EDGAR don't
+        for (y, m, d) in [(2019, 4, 19), (2023, 4, 7), (2024, 3, 29)] {
+            assert!(is_business_day(ymd(y, m, d)), "{y}-{m}-{d}");
+        }
+    }
+
+    #[test]
+    fn special_closures_are_not_business_days() {
+        for &(y, m, d) in SPECIAL_CLOSURES {
+            let date = ymd(y, m, d);
+            assert!(!matches!(date.weekday(), Weekday::Sat | Weekday::Sun), "{date} is a weekend");
+            assert!(!is_business_day(date), "{date}");
+        }
+
+        // the regular Christmas rules alone keep these open
+        assert!(is_business_day(ymd(2021, 12, 23)));
+        assert!(is_business_day(ymd(2023, 12, 26)));
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::NaiveDate;
+use minidom::{Element, NSChoice};
+use regex::Regex;
+use tracing::warn;
+
+use super::{Edgar, IndexEntry};
+
+const FEED_SIZE: usize = 100;
+
+/// One filing announced on the "latest filings" Atom feed.
+#[derive(Debug, Clone)]
+pub struct FeedEntry {
+    pub access_no: String,
+    pub cik: String,
+    pub name: String,
+    pub form_type: String,
+    pub file_date: NaiveDate,
+}
+
+impl FeedEntry {
+    pub fn index_entry(&self) -> IndexEntry {
+        IndexEntry {
+            company_cik: self.cik.clone(),
+            company_name: self.name.clone(),
+            form_type: self.form_type.clone(),
+            file_date: self.file_date,
+            filepath: format!("edgar/data/{}/{}.txt", self.cik, self.access_no),
+        }
+    }
+}
+
+fn child_text(el: &Element, name: &str) -> Option<String> {
+    el.get_child(name, NSChoice::Any).map(|c| c.text().trim().to_string())
+}
+
+fn parse_entry(el: &Element) -> Option<FeedEntry> {
+    let access_pattern = Regex::new(r"accession-number=([0-9]{10}-[0-9]{2}-[0-9]{6})").unwrap();
+    let cik_pattern = Regex::new(r"/edgar/data/([0-9]+)/").unwrap();
+    let name_pattern = Regex::new(r"^.+? - (.+?) \(").unwrap();
+
+    let id = child_text(el, "id")?;
+    let access_no = access_pattern.captures(&id)?[1].to_string();
+
+    let href = el.get_child("link", NSChoice::Any)?.attr("href")?;
+    let cik = cik_pattern.captures(href)?[1].to_string();
+
+    let form_type = el
+        .get_child("category", NSChoice::Any)?
+        .attr("term")?
+        .to_uppercase();
+
+    let title = child_text(el, "title").unwrap_or_default();
+    let name = name_pattern
+        .captures(&title)
+        .map(|cap| cap[1].to_uppercase())
+        .unwrap_or_default();
+
+    let updated = child_text(el, "updated")?;
+    let file_date = NaiveDate::parse_from_str(updated.get(..10)?, "%Y-%m-%d").ok()?;
+
+    Some(FeedEntry { access_no, cik, name, form_type, file_date })
+}
+
+/// Entries of a getcurrent Atom document. Every filing is listed once per
+/// filer (issuer and reporting owner) so duplicates are dropped here.
+pub fn parse_feed(xml: &str) -> Result<Vec<FeedEntry>, minidom::Error> {
+    // the feed declares ISO-8859-1 which minidom refuses, but the body has
+    // already been decoded to a UTF-8 string by the time it gets here
+    let xml = xml.trim_start();
+    let xml = match xml.strip_prefix("<?xml").and_then(|rest| rest.split_once("?>")) {
+        Some((_, body)) => body.trim_start(),
+        None => xml,
+    };
+
+    let root: Element = xml.parse()?;
+    let mut entries: Vec<FeedEntry> = Vec::new();
+
+    for el in root.children().filter(|c| c.is("entry", NSChoice::Any)) {
+        match parse_entry(el) {
+            Some(entry) if !entries.iter().any(|e| e.access_no == entry.access_no) => entries.push(entry),
+            Some(_) => (),
+            None => warn!("Skipping unreadable feed entry"),
+        }
+    }
+
+    Ok(entries)
+}
+
+impl Edgar {
+    /// Most recent filings of `form_type` from EDGAR's current events feed.
+    pub async fn get_current(&self, form_type: &str) -> Result<Vec<FeedEntry>, Box<dyn std::error::Error>> {
+        let mut url = self.url("cgi-bin/browse-edgar");
+        url.query_pairs_mut()
+            .append_pair("action", "getcurrent")
+            .append_pair("type", form_type)
+            .append_pair("owner", "include")
+            .append_pair("count", &FEED_SIZE.to_string())
+            .append_pair("output", "atom");
+
+        let body = self.get_text("feed", url).await?;
+        let entries = parse_feed(&body)?
+            .into_iter()
+            // `type=4` also matches 40-F, 424B2 and friends
+            .filter(|e| e.form_type.eq_ignore_ascii_case(form_type))
+            .collect();
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/edgar/cgi-bin/browse-edgar"));
+
+    #[test]
+    fn feed_lists_each_filing_once() {
+        let entries = parse_feed(FEED).unwrap();
+
+        let access_nos: Vec<_> = entries.iter().map(|entry| entry.access_no.as_str()).collect();
+        assert_eq!(access_nos, ["0001209191-23-004512", "0000950103-23-000712"]);
+
+        let entry = &entries[0];
+        assert_eq!(entry.cik, "320193");
+        assert_eq!(entry.name, "APPLE INC.");
+        assert_eq!(entry.form_type, "4");
+        assert_eq!(entry.file_date, NaiveDate::from_ymd_opt(2023, 1, 17).unwrap());
+        assert_eq!(entry.index_entry().filepath, "edgar/data/320193/0001209191-23-004512.txt");
+    }
+
+    #[test]
+    fn unreadable_entries_are_skipped() {
+        let xml = r#"<feed xmlns="http://www.w3.org/2005/Atom"><entry><title>4 - nobody</title></entry></feed>"#;
+        assert!(parse_feed(xml).unwrap().is_empty());
+        assert!(parse_feed("<feed>").is_err());
+    }
+}
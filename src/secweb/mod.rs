@@ -1,88 +1,218 @@
 mod parser;
 pub mod models;
+pub mod calendar;
+pub mod feed;
+pub mod submissions;
+pub mod tarball;
+
+pub use parser::{FilingDoc, PARSER_VERSION};
+pub use parser::index::IndexEntry;
 
 use std::error::Error;
-use std::io::Write;
-use std::fs::OpenOptions;
-use std::sync::{Arc, Mutex};
+use std::io::{Read, Write};
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::time::Instant;
 use chrono::{NaiveDate, Datelike};
-use reqwest::{RequestBuilder, Client};
-use reqwest::{Url};
+use flate2::read::GzDecoder;
+use reqwest::{Client, Response, StatusCode, Url};
+use tracing::{debug, error};
+
+use parser::index::{extract_index_entries, get_quarter};
+pub use parser::index::parse_entry as parse_index_line;
 
-use parser::index::{IndexEntry, extract_index_entries, get_quarter};
+use crate::{config::config, metrics};
 
 use self::models::FilingTransaction;
-use self::parser::FilingDoc;
-
-const BASEURL: &str = "https://www.sec.gov/Archives/";
-type Db = Arc<Mutex<Vec<FilingTransaction>>>;
-
-pub async fn get_form(entry: &IndexEntry) -> Result<Vec<FilingTransaction>, Box<dyn Error>> {
-    let url = format!("{BASEURL}{}", entry.filepath);
-    println!("url: {url}");
-
-    let client = Client::new();
-    let res = client.get(
-        Url::parse(&url).expect("Failed to parse valid URL")
-    )
-    .header("User-Agent", "Michael Samon mjsamon@icloud.com")
-    .send()
-    .await?;
-
-    let body = res.text().await?;
-    
-    FilingDoc::new(&url, &body)
+
+pub const SEC_BASEURL: &str = "https://www.sec.gov/";
+pub const SEC_DATA_BASEURL: &str = "https://data.sec.gov/";
+
+/// Canonical sec.gov URL of an archive path. This is what gets stored with a
+/// filing no matter which server it was actually fetched from.
+pub fn archives_url(filepath: &str) -> String {
+    format!("{SEC_BASEURL}Archives/{filepath}")
 }
 
-fn save_failed(index_url: &str) {
-    let mut file = OpenOptions::new()
-        .append(true)
-        .open("filings/failed.txt")
-        .unwrap();
+/// Human readable index page of a filing, e.g.
+/// `.../edgar/data/1214156/000120919123004512/0001209191-23-004512-index.html`
+pub fn filing_index_url(cik: &str, access_no: &str) -> String {
+    archives_url(&format!("edgar/data/{}/{}/{}-index.html", cik, access_no.replace('-', ""), access_no))
+}
 
-    if let Err(_) = writeln!(file, "{}", index_url) {
-        println!("Error occurred writing {} to failed.txt", index_url);
+pub fn form_url(entry: &IndexEntry) -> String {
+    archives_url(&entry.filepath)
+}
+
+fn parse_base_url(url: &str) -> Url {
+    let mut url = Url::parse(url).expect("Failed to parse EDGAR base URL");
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
     }
+
+    url
 }
 
-pub async fn process_entries(entries: &[IndexEntry], db: Db, skip: usize, take: usize) -> Result<(), Box<dyn Error>> {
-    for entry in entries.iter().cloned().skip(skip).take(take) {
-        let db = db.clone();
-
-        tokio::spawn(async move {
-            let result = get_form(&entry).await;
-            match result {
-                Ok(mut filings) => {
-                    db.lock()
-                        .and_then(|mut v| Ok(v.append(&mut filings)))
-                        .expect("Could not push to mutex db");
-                },
-                Err(err) => {
-                    println!("Error occurred for filing {}: {:?}", entry.filepath, err);
-                    save_failed(&entry.filepath);
-                }
-            }
-        });
+/// HTTP access to an EDGAR server, the live SEC by default or any mirror
+/// with the same layout (see the `mock_edgar` binary).
+#[derive(Clone)]
+pub struct Edgar {
+    base_url: Url,
+    // the JSON APIs live on their own host
+    data_url: Url,
+    client: Client,
+}
+
+impl Edgar {
+    /// `user_agent` should name the operator and a contact email, the SEC
+    /// blocks clients without one.
+    pub fn new(base_url: &str, user_agent: &str) -> Edgar {
+        let client = Client::builder()
+            .user_agent(user_agent)
+            .build()
+            .expect("Could not build HTTP client");
+
+        Edgar { base_url: parse_base_url(base_url), data_url: parse_base_url(SEC_DATA_BASEURL), client }
+    }
+
+    pub fn with_data_url(mut self, data_url: &str) -> Edgar {
+        self.data_url = parse_base_url(data_url);
+        self
+    }
+
+    /// Uses the identity and URLs of the `[edgar]` config section.
+    pub fn from_config() -> Edgar {
+        let edgar = &config().edgar;
+
+        Self::new(&edgar.base_url, &edgar.user_agent).with_data_url(edgar.data_url())
+    }
+
+    pub fn url(&self, path: &str) -> Url {
+        self.base_url
+            .join(path.trim_start_matches('/'))
+            .expect("Failed to parse valid URL")
+    }
+
+    pub fn data_url(&self, path: &str) -> Url {
+        self.data_url
+            .join(path.trim_start_matches('/'))
+            .expect("Failed to parse valid URL")
     }
 
-    Ok(())
+    /// GETs `url`, recording latency and status code under `kind`.
+    async fn send(&self, kind: &str, url: Url) -> Result<Response, reqwest::Error> {
+        let started = Instant::now();
+        let res = self.client.get(url).send().await;
+
+        let status = match &res {
+            Ok(res) => res.status().as_str().to_string(),
+            Err(_) => "error".to_string(),
+        };
+        metrics::observe_request(kind, &status, started.elapsed());
+
+        res
+    }
+
+    pub async fn get_text(&self, kind: &str, url: Url) -> Result<String, reqwest::Error> {
+        self.send(kind, url)
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+    }
+
+    pub async fn get_form(&self, entry: &IndexEntry) -> Result<Vec<FilingTransaction>, Box<dyn Error>> {
+        let body = self.fetch_form(entry).await?;
+
+        FilingDoc::new(&form_url(entry), &body)
+    }
+
+    pub async fn fetch_form(&self, entry: &IndexEntry) -> Result<String, reqwest::Error> {
+        let url = self.url(&format!("Archives/{}", entry.filepath));
+        debug!(%url, "Sending request");
+
+        self.get_text("filing", url).await
+    }
+
+    /// Raw daily master index, `None` when EDGAR has no index for that date.
+    pub async fn fetch_daily_index(&self, date: NaiveDate) -> Result<Option<String>, reqwest::Error> {
+        let index_url = self.url(&format!(
+            "Archives/edgar/daily-index/{}/{}/{}",
+            date.year(), get_quarter(date), daily_index_name(date)));
+
+        debug!(url = %index_url, "Sending request");
+        let res = self.send("daily_index", index_url).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        res.error_for_status()?.text().await.map(Some)
+    }
+
+    /// Quarterly master index covering `date`, `None` when EDGAR has none.
+    /// The gzipped copy is tried first since it is a fraction of the size.
+    pub async fn fetch_full_index(&self, date: NaiveDate) -> Result<Option<String>, Box<dyn Error>> {
+        let gz_url = self.url(&full_index_name(date).replace("master.idx", "master.gz"));
+        debug!(url = %gz_url, "Sending request");
+
+        let res = self.send("full_index", gz_url).await?;
+        if res.status().is_success() {
+            let bytes = res.bytes().await?;
+            let mut body = String::new();
+            GzDecoder::new(&bytes[..]).read_to_string(&mut body)?;
+
+            return Ok(Some(body));
+        }
+
+        let url = self.url(&full_index_name(date));
+        debug!(%url, "Sending request");
+
+        let res = self.send("full_index", url).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        Ok(Some(res.error_for_status()?.text().await?))
+    }
+
+    pub async fn get_daily_entries(&self, date: NaiveDate, form_types: &[String]) -> Result<Vec<IndexEntry>, Box<dyn Error>> {
+        let body = self.fetch_daily_index(date).await?;
+
+        Ok(body.map(|body| parse_daily_index(&body, form_types)).unwrap_or_default())
+    }
 }
 
-pub async fn get_daily_entries(date: NaiveDate) -> Result<Vec<IndexEntry>, Box<dyn Error>> {
-    let flat_date = NaiveDate::format(&date, "%Y%m%d").to_string();
-    let qtr = get_quarter(date);
-    let index_url = format!(
-        "https://www.sec.gov/Archives/edgar/daily-index/{}/{}/master.{}.idx"
-        , date.year(), qtr, flat_date);
+/// failed.txt in the filings directory.
+pub fn failed_path() -> PathBuf {
+    config().storage.filings_dir.join("failed.txt")
+}
+
+/// Records an entry that could not be fetched or parsed as an index line, so
+/// `retry-failed` can send it through the pipeline again.
+pub fn save_failed(entry: &IndexEntry) {
+    fs::create_dir_all(&config().storage.filings_dir).expect("Failed to create dir path");
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(failed_path())
+        .unwrap();
+
+    if writeln!(file, "{}", entry.index_line()).is_err() {
+        error!(path = %entry.filepath, "Could not write to failed.txt");
+    }
+}
 
-    let client = Client::new();
-    let request = client.get(
-        Url::parse(&index_url).expect("Failed to parse valid URL")
-    )
-    .header("User-Agent", "Michael Samon mjsamon@icloud.com");
+pub fn daily_index_name(date: NaiveDate) -> String {
+    format!("master.{}.idx", date.format("%Y%m%d"))
+}
 
-    println!("Send request to: {index_url}");
-    let body = RequestBuilder::send(request).await?.text();
+/// Path of the quarterly master index covering `date` relative to the base URL,
+/// also used as its archive key.
+pub fn full_index_name(date: NaiveDate) -> String {
+    format!("Archives/edgar/full-index/{}/{}/master.idx", date.year(), get_quarter(date))
+}
 
-    Ok(extract_index_entries(&body.await.unwrap()))
+pub fn parse_daily_index(body: &str, form_types: &[String]) -> Vec<IndexEntry> {
+    extract_index_entries(body, form_types)
 }
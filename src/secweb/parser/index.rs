@@ -11,21 +11,38 @@ pub struct IndexEntry {
     pub filepath: String
 }
 
-pub fn extract_index_entries(input: &str) -> Vec<IndexEntry> {
+impl IndexEntry {
+    /// Accession number taken from the filing path, e.g.
+    /// `edgar/data/1000045/0001000045-23-000003.txt` -> `0001000045-23-000003`
+    pub fn access_no(&self) -> String {
+        let name = self.filepath
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+
+        name.trim_end_matches(".txt").to_string()
+    }
+}
+
+/// Entries of a master index with one of `form_types`, e.g. `4` or `4/A`.
+pub fn extract_index_entries(input: &str, form_types: &[String]) -> Vec<IndexEntry> {
     let mut entries = Vec::<IndexEntry>::new();
 
-    let re = Regex::new(r"\d+\|.*\|4\|.*").unwrap();
-    for entry in re.find_iter(input) {
-        let entry = parse_entry(entry.as_str());
-        if entry.is_some() {
-            entries.push(entry.unwrap());
+    // data lines start with the CIK, which skips the header block
+    let re = Regex::new(r"(?m)^\d+\|.*$").unwrap();
+    for line in re.find_iter(input) {
+        if let Some(entry) = parse_entry(line.as_str().trim_end()) {
+            if form_types.iter().any(|t| t.eq_ignore_ascii_case(&entry.form_type)) {
+                entries.push(entry);
+            }
         }
     }
 
     entries
 }
 
-fn parse_entry(entry: &str) -> Option<IndexEntry> {
+/// One `CIK|Company Name|Form Type|Date Filed|Filename` line.
+pub fn parse_entry(entry: &str) -> Option<IndexEntry> {
     let values: Vec<&str> = entry.split('|').collect();
 
     if values.len() == 5 {
@@ -33,8 +50,10 @@ fn parse_entry(entry: &str) -> Option<IndexEntry> {
             company_cik: values[0].to_uppercase(),
             company_name: values[1].to_uppercase(),
             form_type: values[2].to_uppercase(),
+            // daily indexes use 20230117, quarterly full indexes 2023-01-17
             file_date: NaiveDate::parse_from_str(values[3], "%Y%m%d")
-                .expect("Invalid date string"),
+                .or_else(|_| NaiveDate::parse_from_str(values[3], "%Y-%m-%d"))
+                .ok()?,
             filepath: values[4].to_string()
         })
     } else {
@@ -42,6 +61,15 @@ fn parse_entry(entry: &str) -> Option<IndexEntry> {
     }
 }
 
+impl IndexEntry {
+    /// The entry as a daily index line, readable again by `parse_entry`.
+    pub fn index_line(&self) -> String {
+        format!(
+            "{}|{}|{}|{}|{}",
+            self.company_cik, self.company_name, self.form_type, self.file_date.format("%Y%m%d"), self.filepath)
+    }
+}
+
 pub fn get_quarter(date: NaiveDate) -> String {
     let q1 = NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap();
     let q2 = NaiveDate::from_ymd_opt(date.year(), 4, 1).unwrap();
@@ -57,4 +85,61 @@ pub fn get_quarter(date: NaiveDate) -> String {
     } else {
         "QTR4".to_string()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAILY: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/edgar/Archives/edgar/daily-index/2023/QTR1/master.20230117.idx"));
+    const FULL: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/edgar/Archives/edgar/full-index/2023/QTR1/master.idx"));
+
+    fn forms(types: &[&str]) -> Vec<String> {
+        types.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn daily_index_entries_of_the_form_types() {
+        let entries = extract_index_entries(DAILY, &forms(&["4"]));
+
+        let access_nos: Vec<_> = entries.iter().map(IndexEntry::access_no).collect();
+        assert_eq!(access_nos, ["0001209191-23-004512", "0001209191-23-004512"]);
+
+        let issuer = &entries[0];
+        assert_eq!(issuer.company_cik, "320193");
+        assert_eq!(issuer.company_name, "APPLE INC.");
+        assert_eq!(issuer.file_date, NaiveDate::from_ymd_opt(2023, 1, 17).unwrap());
+        assert_eq!(entries[1].company_cik, "1214156");
+
+        // form types match whatever their case
+        assert_eq!(extract_index_entries(DAILY, &forms(&["8-k"])).len(), 1);
+        assert!(extract_index_entries(DAILY, &forms(&["10-K"])).is_empty());
+    }
+
+    #[test]
+    fn full_index_dates_have_dashes() {
+        let entries = extract_index_entries(FULL, &forms(&["4", "8-K"]));
+
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|entry| entry.file_date == NaiveDate::from_ymd_opt(2023, 1, 17).unwrap()));
+    }
+
+    #[test]
+    fn index_lines_read_back() {
+        let entry = parse_entry("1214156|COOK TIMOTHY D|4|2023-01-17|edgar/data/1214156/0001209191-23-004512.txt").unwrap();
+        let again = parse_entry(&entry.index_line()).unwrap();
+
+        assert_eq!(again.index_line(), entry.index_line());
+        assert_eq!(again.file_date, entry.file_date);
+        assert!(parse_entry("1214156|COOK TIMOTHY D|4|20230117").is_none());
+    }
+
+    #[test]
+    fn quarters() {
+        let quarter = |m, d| get_quarter(NaiveDate::from_ymd_opt(2023, m, d).unwrap());
+        assert_eq!(quarter(1, 1), "QTR1");
+        assert_eq!(quarter(3, 31), "QTR1");
+        assert_eq!(quarter(4, 1), "QTR2");
+        assert_eq!(quarter(9, 30), "QTR3");
+        assert_eq!(quarter(12, 31), "QTR4");
+    }
+}
@@ -7,9 +7,14 @@ use regex::Regex;
 use self::xmlfiling::XMLFiling;
 use super::models::FilingTransaction;
 
+/// Bump whenever a parser change alters the transactions extracted from a
+/// filing, so rows can be traced back to the code that produced them.
+pub const PARSER_VERSION: i32 = 1;
+
 pub struct FilingDoc;
 
 impl FilingDoc {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(url: &str, content: &str) -> Result<Vec<FilingTransaction>, Box<dyn Error>>{
         let mut filing = XMLFiling::new(url);
         let content = Self::extract_xml(content);
@@ -35,9 +40,9 @@ impl FilingDoc {
             })
             .expect("XML regex match failed");
 
-        let result = result
-            .replace("<ownershipDocument>", "<ownershipDocument xmlns=\"\">");
+        
         
         result
+            .replace("<ownershipDocument>", "<ownershipDocument xmlns=\"\">")
     }
 }
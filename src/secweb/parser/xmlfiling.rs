@@ -3,7 +3,7 @@ use std::{string::String, error::Error};
 use minidom::{Element, NSChoice};
 use regex::Regex;
 
-use crate::secweb::models::{Relationship, FilingTransaction};
+use crate::secweb::{filing_index_url, models::{Relationship, FilingTransaction}};
 
 #[derive(Debug, Default)]
 struct XMLNode {
@@ -17,7 +17,7 @@ impl XMLNode {
             text = el.get_child("value", NSChoice::Any).unwrap().text().trim().to_uppercase();
         }
         
-        XMLNode { text: text }
+        XMLNode { text }
     }
 
     pub fn parse_num(&self) -> f32 {
@@ -30,18 +30,16 @@ impl XMLNode {
 }
 
 pub struct XMLFiling {
-    pub transactions: Vec<FilingTransaction>,
     pub url: String
 }
 
 impl XMLFiling {
     pub fn new(url: &str) -> XMLFiling {
-        XMLFiling {url: url.to_string(), transactions: Vec::<FilingTransaction>::new() }
+        XMLFiling { url: url.to_string() }
     }
 
     pub fn get_web_url(&self, owner_cik: &str) -> String {
-        let bare_num = self.parse_access_num().replace("-", "");
-        format!("https://www.sec.gov/Archives/edgar/data/{}/{}/{}-index.html", owner_cik, bare_num, self.parse_access_num())
+        filing_index_url(owner_cik, &self.parse_access_num())
     }
 
     fn parse_access_num(&self) -> String {
@@ -56,19 +54,19 @@ impl XMLFiling {
     fn get_relationship(node: &Element) -> Vec<Relationship> {
         let mut relationships = Vec::<Relationship>::new();
     
-        if Self::traverse(&node, &["reportingOwner", "reportingOwnerRelationship", "isDirector"]).unwrap_or_default().text == "1" {
+        if Self::traverse(node, &["reportingOwner", "reportingOwnerRelationship", "isDirector"]).unwrap_or_default().text == "1" {
             relationships.push(Relationship::DIRECTOR);
         }
     
-        if Self::traverse(&node, &["reportingOwner", "reportingOwnerRelationship", "isOfficer"]).unwrap_or_default().text == "1" {
+        if Self::traverse(node, &["reportingOwner", "reportingOwnerRelationship", "isOfficer"]).unwrap_or_default().text == "1" {
             relationships.push(Relationship::OFFICER);
         }
     
-        if Self::traverse(&node, &["reportingOwner", "reportingOwnerRelationship", "isTenPercentOwner"]).unwrap_or_default().text == "1" {
+        if Self::traverse(node, &["reportingOwner", "reportingOwnerRelationship", "isTenPercentOwner"]).unwrap_or_default().text == "1" {
             relationships.push(Relationship::TENPERC);
         }
     
-        if Self::traverse(&node, &["reportingOwner", "reportingOwnerRelationship", "isOther"]).unwrap_or_default().text == "1" {
+        if Self::traverse(node, &["reportingOwner", "reportingOwnerRelationship", "isOther"]).unwrap_or_default().text == "1" {
             relationships.push(Relationship::OTHER);
         }
     
@@ -81,21 +79,13 @@ impl XMLFiling {
 
         for tag in path {
             pos = prev.get_child(tag, NSChoice::Any);
-            if pos.is_some() {
-                prev = pos.unwrap();
-            } else {
-                return Option::None;
+            match pos {
+                Some(el) => prev = el,
+                None => return Option::None,
             }
         }
         
-        match pos {
-            Some(el) => {
-                return Some(XMLNode::new(el));
-            },
-            None => {
-                return Option::None;
-            }
-        }
+        pos.map(XMLNode::new)
     }
 
     pub fn extract_transactions(&mut self, xml_input: &str) -> Result<Vec<FilingTransaction>, Box<dyn Error>>{
@@ -113,35 +103,36 @@ impl XMLFiling {
         let form_date = Self::traverse(&root, &["periodOfReport"]).unwrap().parse_date();
         let web_url = self.get_web_url(&rpt_owner_cik);
 
-        let table = root
-            .get_child("nonDerivativeTable", NSChoice::Any)
-            .expect("Filing should have a non derivative table");
+        // filings reporting only derivative holdings have no rows for us
+        let Some(table) = root.get_child("nonDerivativeTable", NSChoice::Any) else {
+            return Ok(transactions);
+        };
 
         for child in table.children() {
             if child.is("nonDerivativeTransaction", NSChoice::Any) {
-                let shares_traded = Self::traverse(&child, &["transactionAmounts", "transactionShares"]).unwrap().parse_num();
-                let avg_price = Self::traverse(&child, &["transactionAmounts", "transactionPricePerShare"]).unwrap().parse_num();
+                let shares_traded = Self::traverse(child, &["transactionAmounts", "transactionShares"]).unwrap().parse_num();
+                let avg_price = Self::traverse(child, &["transactionAmounts", "transactionPricePerShare"]).unwrap().parse_num();
 
                 let filing = FilingTransaction {
                     web_url: web_url.clone(),
                     form_url: self.url.clone(),
                     access_no: access_no.clone(),
-                    form_date: form_date.clone(),
+                    form_date,
                     company_cik:  company_cik.clone(),
                     owner_cik: rpt_owner_cik.clone(),
                     form_type: form_type.clone(),
                     company: company.clone(),
                     symbol: symbol.clone(),
                     owner: owner.clone(),
-                    shares_traded: shares_traded,
-                    avg_price: avg_price,
+                    shares_traded,
+                    avg_price,
                     amount: shares_traded * avg_price,
-                    shares_owned: Self::traverse(&child, &["postTransactionAmounts", "sharesOwnedFollowingTransaction"]).unwrap().parse_num(),
-                    trans_date: Self::traverse(&child, &["transactionDate"]).unwrap().parse_date(),
+                    shares_owned: Self::traverse(child, &["postTransactionAmounts", "sharesOwnedFollowingTransaction"]).unwrap().parse_num(),
+                    trans_date: Self::traverse(child, &["transactionDate"]).unwrap().parse_date(),
                     relationship: relationships.clone(),
-                    action_code: Self::traverse(&child, &["transactionAmounts", "transactionAcquiredDisposedCode"]).unwrap().text,
-                    ownership_code: Self::traverse(&child, &["ownershipNature", "directOrIndirectOwnership"]).unwrap().text,
-                    trans_code: Self::traverse(&child, &["transactionCoding", "transactionCode"]).unwrap().text
+                    action_code: Self::traverse(child, &["transactionAmounts", "transactionAcquiredDisposedCode"]).unwrap().text,
+                    ownership_code: Self::traverse(child, &["ownershipNature", "directOrIndirectOwnership"]).unwrap().text,
+                    trans_code: Self::traverse(child, &["transactionCoding", "transactionCode"]).unwrap().text
                 };
 
                 transactions.push(filing);
@@ -0,0 +1,191 @@
+use std::error::Error;
+use chrono::NaiveDate;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tracing::debug;
+
+use super::{Edgar, IndexEntry};
+
+/// `submissions/CIK##########.json` from data.sec.gov, the filing history and
+/// company details EDGAR keeps for every filer, issuer or reporting owner.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Submissions {
+    pub cik: String,
+    #[serde(default)]
+    pub entity_type: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub sic: String,
+    #[serde(default)]
+    pub sic_description: String,
+    #[serde(default)]
+    pub tickers: Vec<String>,
+    // exchanges line up with tickers and are null for OTC listings
+    #[serde(default)]
+    pub exchanges: Vec<Option<String>>,
+    #[serde(default)]
+    pub former_names: Vec<FormerName>,
+    pub filings: Filings,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormerName {
+    pub name: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl FormerName {
+    // e.g. 2007-01-10T00:00:00.000Z
+    fn date(value: &Option<String>) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(value.as_deref()?.get(..10)?, "%Y-%m-%d").ok()
+    }
+
+    pub fn date_from(&self) -> Option<NaiveDate> {
+        Self::date(&self.from)
+    }
+
+    pub fn date_to(&self) -> Option<NaiveDate> {
+        Self::date(&self.to)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Filings {
+    pub recent: FilingColumns,
+    #[serde(default)]
+    pub files: Vec<SubmissionFile>,
+}
+
+/// Older history is split off into extra files once `recent` holds 1000
+/// filings or a year's worth, whichever is more.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmissionFile {
+    pub name: String,
+    #[serde(default)]
+    pub filing_count: usize,
+}
+
+/// Filings stored column wise, one array per field.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilingColumns {
+    #[serde(default)]
+    pub accession_number: Vec<String>,
+    #[serde(default)]
+    pub filing_date: Vec<String>,
+    #[serde(default)]
+    pub form: Vec<String>,
+}
+
+impl FilingColumns {
+    /// Index entries of the filings with one of `form_types`, listed under `cik`.
+    pub fn entries(&self, cik: &str, name: &str, form_types: &[String]) -> Vec<IndexEntry> {
+        let cik = cik.trim_start_matches('0');
+
+        self.accession_number.iter()
+            .zip(&self.filing_date)
+            .zip(&self.form)
+            .filter(|(_, form)| form_types.iter().any(|t| t.eq_ignore_ascii_case(form)))
+            .filter_map(|((access_no, file_date), form)| Some(IndexEntry {
+                company_cik: cik.to_string(),
+                company_name: name.to_uppercase(),
+                form_type: form.to_uppercase(),
+                file_date: NaiveDate::parse_from_str(file_date, "%Y-%m-%d").ok()?,
+                filepath: format!("edgar/data/{cik}/{access_no}.txt"),
+            }))
+            .collect()
+    }
+}
+
+fn submissions_name(cik: &str) -> String {
+    format!("submissions/CIK{:0>10}.json", cik.trim_start_matches('0'))
+}
+
+impl Edgar {
+    /// Submissions document of `cik`, `None` when EDGAR doesn't know the CIK.
+    pub async fn get_submissions(&self, cik: &str) -> Result<Option<Submissions>, Box<dyn Error>> {
+        let url = self.data_url(&submissions_name(cik));
+        debug!(%url, "Sending request");
+
+        let res = self.send("submissions", url).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        Ok(Some(res.error_for_status()?.json().await?))
+    }
+
+    pub async fn get_submission_file(&self, file: &SubmissionFile) -> Result<FilingColumns, Box<dyn Error>> {
+        let url = self.data_url(&format!("submissions/{}", file.name));
+        debug!(%url, "Sending request");
+
+        Ok(self.send("submissions", url).await?.error_for_status()?.json().await?)
+    }
+
+    /// Every filing of `form_types` in the history of `cik`, following the
+    /// paged files, together with the submissions document itself.
+    pub async fn get_filer_entries(&self, cik: &str, form_types: &[String]) -> Result<Option<(Submissions, Vec<IndexEntry>)>, Box<dyn Error>> {
+        let Some(submissions) = self.get_submissions(cik).await? else {
+            return Ok(None);
+        };
+
+        let mut entries = submissions.filings.recent.entries(&submissions.cik, &submissions.name, form_types);
+        for file in &submissions.filings.files {
+            let page = self.get_submission_file(file).await?;
+            entries.append(&mut page.entries(&submissions.cik, &submissions.name, form_types));
+        }
+
+        Ok(Some((submissions, entries)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBMISSIONS: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/edgar/submissions/CIK0000320193.json"));
+    const OLDER: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/edgar/submissions/CIK0000320193-submissions-001.json"));
+
+    #[test]
+    fn submissions_company_details() {
+        let submissions: Submissions = serde_json::from_str(SUBMISSIONS).unwrap();
+
+        assert_eq!(submissions.cik, "320193");
+        assert_eq!(submissions.name, "Apple Inc.");
+        assert_eq!(submissions.tickers, ["AAPL"]);
+        assert_eq!(submissions.exchanges, [Some("Nasdaq".to_string())]);
+
+        let former = &submissions.former_names[0];
+        assert_eq!(former.name, "APPLE INC");
+        assert_eq!(former.date_from(), NaiveDate::from_ymd_opt(2007, 1, 10));
+        assert_eq!(former.date_to(), NaiveDate::from_ymd_opt(2019, 8, 5));
+
+        let files: Vec<_> = submissions.filings.files.iter().map(|file| (file.name.as_str(), file.filing_count)).collect();
+        assert_eq!(files, [("CIK0000320193-submissions-001.json", 2)]);
+    }
+
+    #[test]
+    fn submissions_entries_of_the_form_types() {
+        let submissions: Submissions = serde_json::from_str(SUBMISSIONS).unwrap();
+        let entries = submissions.filings.recent.entries("0000320193", &submissions.name, &["4".to_string()]);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].company_cik, "320193");
+        assert_eq!(entries[0].company_name, "APPLE INC.");
+        assert_eq!(entries[0].file_date, NaiveDate::from_ymd_opt(2023, 1, 17).unwrap());
+        assert_eq!(entries[0].filepath, "edgar/data/320193/0001209191-23-004512.txt");
+
+        let older: FilingColumns = serde_json::from_str(OLDER).unwrap();
+        assert_eq!(older.entries("320193", "Apple Inc.", &["10-q".to_string()]).len(), 1);
+    }
+
+    #[test]
+    fn submissions_paths_pad_the_cik() {
+        assert_eq!(submissions_name("320193"), "submissions/CIK0000320193.json");
+        assert_eq!(submissions_name("0000320193"), "submissions/CIK0000320193.json");
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path};
+use chrono::{NaiveDate, Datelike};
+use flate2::read::GzDecoder;
+use regex::Regex;
+use reqwest::StatusCode;
+use tracing::{debug, warn};
+
+use super::{parser::index::get_quarter, Edgar, IndexEntry};
+
+// enough to hold the submission type, which comes right after the accession number
+const HEAD_SIZE: u64 = 4096;
+
+/// Path of the daily feed tarball for `date` relative to the base URL. It
+/// holds every submission disseminated that day as one `.nc` file each.
+pub fn feed_tarball_name(date: NaiveDate) -> String {
+    format!(
+        "Archives/edgar/Feed/{}/{}/{}.nc.tar.gz",
+        date.year(), get_quarter(date), date.format("%Y%m%d"))
+}
+
+/// One `.nc` submission from a feed tarball.
+#[derive(Debug, Clone)]
+pub struct NcSubmission {
+    pub access_no: String,
+    pub form_type: String,
+    pub file_date: NaiveDate,
+    pub cik: String,
+    pub name: String,
+    pub body: String,
+}
+
+impl NcSubmission {
+    /// Reads the SGML header of a submission. The issuer is preferred over the
+    /// first filer so ownership forms land under the same path as the indexes
+    /// list them.
+    pub fn parse(body: String) -> Option<NcSubmission> {
+        let header = body.split("<DOCUMENT>").next().unwrap_or_default();
+        let tag = |name: &str, text: &str| {
+            Regex::new(&format!(r"(?m)^<{name}>(.+?)\r?$"))
+                .unwrap()
+                .captures(text)
+                .map(|cap| cap[1].trim().to_string())
+        };
+
+        let access_no = tag("ACCESSION-NUMBER", header)?;
+        let form_type = tag("TYPE", header)?.to_uppercase();
+        let file_date = NaiveDate::parse_from_str(&tag("FILING-DATE", header)?, "%Y%m%d").ok()?;
+
+        let filer = header.find("<ISSUER>").map(|i| &header[i..]).unwrap_or(header);
+        let cik = tag("CIK", filer)?.trim_start_matches('0').to_string();
+        let name = tag("CONFORMED-NAME", filer).unwrap_or_default().to_uppercase();
+
+        Some(NcSubmission { access_no, form_type, file_date, cik, name, body })
+    }
+
+    pub fn index_entry(&self) -> IndexEntry {
+        IndexEntry {
+            company_cik: self.cik.clone(),
+            company_name: self.name.clone(),
+            form_type: self.form_type.clone(),
+            file_date: self.file_date,
+            filepath: format!("edgar/data/{}/{}.txt", self.cik, self.access_no),
+        }
+    }
+}
+
+fn head_form_type(head: &str) -> Option<String> {
+    Regex::new(r"(?m)^<TYPE>(.+?)\r?$")
+        .unwrap()
+        .captures(head)
+        .map(|cap| cap[1].trim().to_uppercase())
+}
+
+/// Calls `f` with every submission of one of `form_types` in a gzipped feed
+/// tarball, returning how many submissions it held in total. Other form types
+/// are skipped after reading their first few KB.
+pub fn read_feed_tarball<R: Read, F: FnMut(NcSubmission)>(reader: R, form_types: &[String], mut f: F) -> io::Result<usize> {
+    let mut tar = tar::Archive::new(GzDecoder::new(reader));
+    let mut total = 0;
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let is_nc = entry.path()?.extension().is_some_and(|ext| ext == "nc");
+        if !entry.header().entry_type().is_file() || !is_nc {
+            continue;
+        }
+
+        total += 1;
+        let mut raw = Vec::new();
+        entry.by_ref().take(HEAD_SIZE).read_to_end(&mut raw)?;
+
+        let wanted = head_form_type(&String::from_utf8_lossy(&raw))
+            .is_some_and(|t| form_types.iter().any(|f| f.eq_ignore_ascii_case(&t)));
+        if !wanted {
+            continue;
+        }
+
+        entry.read_to_end(&mut raw)?;
+        match NcSubmission::parse(String::from_utf8_lossy(&raw).into_owned()) {
+            Some(submission) => f(submission),
+            None => warn!(path = %entry.path()?.display(), "Skipping unreadable submission"),
+        }
+    }
+
+    Ok(total)
+}
+
+impl Edgar {
+    /// Downloads the feed tarball for `date` to `dest`, streaming it since busy
+    /// days run into gigabytes. Returns false when EDGAR has none for that day.
+    pub async fn download_feed_tarball(&self, date: NaiveDate, dest: &Path) -> Result<bool, Box<dyn Error>> {
+        let url = self.url(&feed_tarball_name(date));
+        debug!(%url, "Sending request");
+
+        let mut res = self.send("tarball", url).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        res = res.error_for_status()?;
+
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let part = dest.with_extension("part");
+        let mut file = File::create(&part)?;
+        while let Some(chunk) = res.chunk().await? {
+            file.write_all(&chunk)?;
+        }
+        file.sync_all()?;
+        fs::rename(&part, dest)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARBALL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/edgar/Archives/edgar/Feed/2023/QTR1/20230117.nc.tar.gz");
+
+    #[test]
+    fn tarball_submissions_of_the_form_types() {
+        let mut found = Vec::new();
+        let total = read_feed_tarball(File::open(TARBALL).unwrap(), &["4".to_string()], |nc| found.push(nc)).unwrap();
+
+        assert_eq!(total, 2);
+        assert_eq!(found.len(), 1);
+
+        // listed under the issuer rather than the reporting owner
+        let nc = &found[0];
+        assert_eq!(nc.access_no, "0001209191-23-004512");
+        assert_eq!(nc.form_type, "4");
+        assert_eq!(nc.file_date, NaiveDate::from_ymd_opt(2023, 1, 17).unwrap());
+        assert_eq!(nc.cik, "320193");
+        assert_eq!(nc.name, "APPLE INC.");
+        assert_eq!(nc.index_entry().filepath, "edgar/data/320193/0001209191-23-004512.txt");
+        assert!(nc.body.contains("<ownershipDocument>"));
+    }
+
+    #[test]
+    fn feed_tarball_paths() {
+        let date = NaiveDate::from_ymd_opt(2023, 1, 17).unwrap();
+        assert_eq!(feed_tarball_name(date), "Archives/edgar/Feed/2023/QTR1/20230117.nc.tar.gz");
+    }
+}
@@ -0,0 +1,110 @@
+//! Crawls the fixture day from `mock_edgar` with the real binaries and checks
+//! what the JSON sink wrote.
+
+use std::{
+    env, fs,
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+    thread,
+    time::Duration};
+use spysec::secweb::models::FilingTransaction;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/edgar");
+
+/// `mock_edgar` serving the fixtures on a free port, stopped when dropped.
+struct MockEdgar {
+    child: Child,
+    addr: String,
+}
+
+impl MockEdgar {
+    fn start() -> MockEdgar {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let child = Command::new(env!("CARGO_BIN_EXE_mock_edgar"))
+            .args([FIXTURES, &addr])
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Could not start mock_edgar");
+
+        // stopped by drop from here on, also when it never comes up
+        let mock = MockEdgar { child, addr };
+        for _ in 0..100 {
+            if TcpStream::connect(&mock.addr).is_ok() {
+                return mock;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        panic!("mock_edgar did not listen on {}", mock.addr);
+    }
+}
+
+impl Drop for MockEdgar {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// `spysec` with everything pointed at `dir` and the mock, whatever the
+/// environment running the tests has set.
+fn spysec(dir: &Path, edgar: &MockEdgar) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_spysec"));
+    cmd.current_dir(dir)
+        .env_remove("DATABASE_URL")
+        .env_remove("EDGAR_BASE_URL")
+        .env_remove("EDGAR_DATA_URL")
+        .env_remove("SPYSEC_CONFIG")
+        .env("SPYSEC_EDGAR_USER_AGENT", "spysec tests tests@example.com")
+        .env("SPYSEC_EDGAR_BASE_URL", format!("http://{}/", edgar.addr))
+        .env("SPYSEC_DATABASE_URL", format!("sqlite://{}", dir.join("spysec.db").display()))
+        .env("SPYSEC_STORAGE_FILINGS_DIR", dir.join("filings"))
+        .env("SPYSEC_STORAGE_ARCHIVE_DIR", dir.join("archive"));
+    cmd
+}
+
+fn scratch_dir() -> PathBuf {
+    let dir = env::temp_dir().join(format!("spysec-crawl-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn crawls_a_day_into_json() {
+    let edgar = MockEdgar::start();
+    let dir = scratch_dir();
+
+    let migrated = spysec(&dir, &edgar).arg("migrate").status().unwrap();
+    assert!(migrated.success());
+
+    let crawled = spysec(&dir, &edgar)
+        .args(["crawl", "--from", "2023-01-17", "--to", "2023-01-17", "--sink", "json", "--form", "4"])
+        .status()
+        .unwrap();
+    assert!(crawled.success());
+
+    let json = fs::read_to_string(dir.join("filings/2023/01/20230117-filing.json")).unwrap();
+    let filings: Vec<FilingTransaction> = serde_json::from_str(&json).unwrap();
+
+    // the day's one form 4, fetched once though the index lists it under its
+    // issuer and its owner, the 8-K is left out
+    assert_eq!(filings.len(), 2);
+    for trans in &filings {
+        assert_eq!(trans.access_no, "0001209191-23-004512");
+        assert_eq!(trans.form_type, "4");
+        assert_eq!(trans.company_cik, "0000320193");
+        assert_eq!(trans.symbol, "AAPL");
+        assert_eq!(trans.owner, "COOK TIMOTHY D");
+        assert_eq!(trans.form_url, "https://www.sec.gov/Archives/edgar/data/320193/0001209191-23-004512.txt");
+    }
+
+    let first = &filings[0];
+    assert_eq!(first.trans_date.to_string(), "2023-01-13");
+    assert_eq!(first.trans_code, "S");
+    assert_eq!(first.shares_traded, 1000.0);
+    assert_eq!(first.avg_price, 134.5);
+
+    fs::remove_dir_all(&dir).unwrap();
+}